@@ -0,0 +1,409 @@
+//! Interactive step debugger.
+
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, Write},
+    ops::RangeInclusive,
+};
+
+use peppermint::{
+    debug_info::DebugInfo, parse_int, source::SourceMap, Address, DoubleWord, Program, Statement,
+};
+use peppermint_simulate::TickTalk;
+
+/// Number of statements which can be undone.
+const HISTORY_LIMIT: usize = 10_000;
+
+const HELP: &str = "\
+commands:
+  s, step [n]            execute n statements (default 1)
+  n, next                execute the next instruction, skipping over labels
+  c, continue            run until a breakpoint, watchpoint or the end of the program
+  back [n]               undo n statements (default 1)
+  rewind <label|n>       undo statements until a label or statement number is next
+  b, break <label|n>     stop before executing a label or statement number
+  w, watch <addr>        stop after the value at a memory address changes
+  d, delete <label|n|[addr]>
+                         remove a breakpoint or watchpoint
+  i, info                list breakpoints and watchpoints
+  p, print <acc|pc|carry|[addr]|[addr..addr]>
+                         print a register or memory
+  l, list                show the current source line
+  h, help                show this message
+  q, quit                exit the debugger
+an empty line repeats the last command";
+
+/// Why execution stopped.
+enum Stop {
+    Breakpoint(usize),
+    Watchpoint {
+        addr: Address,
+        old: DoubleWord,
+        new: DoubleWord,
+    },
+    Halted,
+    Stepped,
+}
+
+struct Debugger<'a> {
+    machine: TickTalk<'a, Vec<DoubleWord>>,
+    program: &'a Program,
+    sources: &'a SourceMap,
+    /// Debug info of the image the program was disassembled from, if it wasn't parsed from source.
+    info: Option<&'a DebugInfo>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<Address>,
+}
+
+/// Parse a bracketed memory address or range, like `[0x10]` or `[0x10..0x1f]`.
+fn parse_memory(raw: &str) -> Option<RangeInclusive<Address>> {
+    let inner = raw.strip_prefix('[')?.strip_suffix(']')?;
+    let (start, end) = inner.split_once("..").unwrap_or((inner, inner));
+    let start = parse_int(start)?;
+    let end = parse_int(end)?;
+    Some(start..=end)
+}
+
+impl<'a> Debugger<'a> {
+    /// Resolve a breakpoint location, either a label name or statement number.
+    fn location(&self, raw: &str) -> Option<usize> {
+        if let Some(stat) = parse_int(raw) {
+            return (stat < self.program.statements().len()).then_some(stat);
+        }
+        let name = raw.trim_end_matches(':');
+        self.program
+            .statements()
+            .iter()
+            .position(|stat| matches!(stat, Statement::Label(l) if l == name))
+    }
+
+    /// Describe the statement at `stat`, with its source line if available.
+    fn describe(&self, stat: usize) -> String {
+        let Some(statement) = self.program.statements().get(stat) else {
+            return format!("{stat}: <end of program>");
+        };
+        match self.program.span_of(stat) {
+            Some(span) => {
+                let (file, span) = self.sources.local(&span);
+                let source = self.sources.source(file);
+                let (line, _) = peppermint::diagnostic::line_col(source, span.start);
+                let text = source.lines().nth(line - 1).unwrap_or_default();
+                if file == 0 {
+                    format!("{stat}: line {line} | {}", text.trim_end())
+                } else {
+                    let name = self.sources.name(file);
+                    format!("{stat}: {name} line {line} | {}", text.trim_end())
+                }
+            }
+            None => match self
+                .info
+                .and_then(|info| info.location_of_statement(self.program, stat))
+            {
+                Some(loc) => match &loc.file {
+                    None => format!("{stat}: line {} | {statement:?}", loc.line),
+                    Some(name) => format!("{stat}: {name} line {} | {statement:?}", loc.line),
+                },
+                None => format!("{stat}: {statement:?}"),
+            },
+        }
+    }
+
+    fn print_stop(&self, stop: &Stop) {
+        match stop {
+            Stop::Breakpoint(stat) => println!("breakpoint at statement {stat}"),
+            Stop::Watchpoint { addr, old, new } => {
+                println!("watchpoint [0x{addr:x}]: 0x{old:x} -> 0x{new:x}");
+            }
+            Stop::Halted => {
+                println!("program halted");
+                return;
+            }
+            Stop::Stepped => {}
+        }
+        println!("{}", self.describe(self.machine.program_counter));
+    }
+
+    fn read(&self, addr: Address) -> Option<DoubleWord> {
+        self.machine.memory.get(addr as usize).copied()
+    }
+
+    /// Execute one statement, checking watchpoints.
+    fn step(&mut self) -> Result<Option<Stop>, peppermint_simulate::Error> {
+        let before: Vec<_> = self
+            .watchpoints
+            .iter()
+            .map(|addr| (*addr, self.read(*addr)))
+            .collect();
+        if self.machine.step()? {
+            return Ok(Some(Stop::Halted));
+        }
+        for (addr, old) in before {
+            let new = self.read(addr);
+            if let (Some(old), Some(new)) = (old, new) {
+                if old != new {
+                    return Ok(Some(Stop::Watchpoint { addr, old, new }));
+                }
+            }
+        }
+        if self.machine.halted() {
+            return Ok(Some(Stop::Halted));
+        }
+        Ok(None)
+    }
+
+    fn run_command(&mut self, command: &str, args: &[&str]) -> Result<(), String> {
+        match command {
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(n) => parse_int(n).ok_or("invalid step count")?,
+                    None => 1,
+                };
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    if let Some(s) = self.step().map_err(|e| e.to_string())? {
+                        stop = s;
+                        break;
+                    }
+                }
+                self.print_stop(&stop);
+            }
+            "n" | "next" => {
+                let mut stop = self.step().map_err(|e| e.to_string())?;
+                while stop.is_none()
+                    && matches!(
+                        self.program.statements()[self.machine.program_counter],
+                        Statement::Label(_)
+                    )
+                {
+                    stop = self.step().map_err(|e| e.to_string())?;
+                }
+                self.print_stop(&stop.unwrap_or(Stop::Stepped));
+            }
+            "c" | "continue" => {
+                // always make progress, even if we're sat on a breakpoint
+                let mut stop = self.step().map_err(|e| e.to_string())?;
+                while stop.is_none() {
+                    if self.breakpoints.contains(&self.machine.program_counter) {
+                        stop = Some(Stop::Breakpoint(self.machine.program_counter));
+                        break;
+                    }
+                    stop = self.step().map_err(|e| e.to_string())?;
+                }
+                self.print_stop(&stop.unwrap_or(Stop::Stepped));
+            }
+            "back" => {
+                let count = match args.first() {
+                    Some(n) => parse_int(n).ok_or("invalid step count")?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if self.machine.step_back().is_none() {
+                        println!("no more history");
+                        break;
+                    }
+                }
+                self.print_stop(&Stop::Stepped);
+            }
+            "rewind" => {
+                let raw = args.first().ok_or("expected a label or statement number")?;
+                let stat = self.location(raw).ok_or("no such label or statement")?;
+                if !self.machine.run_back_to(stat) {
+                    println!("statement {stat} not found in history");
+                }
+                self.print_stop(&Stop::Stepped);
+            }
+            "b" | "break" => {
+                let raw = args.first().ok_or("expected a label or statement number")?;
+                let stat = self.location(raw).ok_or("no such label or statement")?;
+                self.breakpoints.insert(stat);
+                println!("breakpoint at {}", self.describe(stat));
+            }
+            "w" | "watch" => {
+                let raw = args.first().ok_or("expected an address")?;
+                let addr = parse_memory(raw)
+                    .or_else(|| {
+                        let addr = parse_int(raw)?;
+                        Some(addr..=addr)
+                    })
+                    .ok_or("invalid address")?;
+                self.watchpoints.extend(addr);
+            }
+            "d" | "delete" => {
+                let raw = args.first().ok_or("expected a breakpoint or watchpoint")?;
+                if let Some(range) = parse_memory(raw) {
+                    for addr in range {
+                        self.watchpoints.remove(&addr);
+                    }
+                } else {
+                    let stat = self.location(raw).ok_or("no such label or statement")?;
+                    self.breakpoints.remove(&stat);
+                }
+            }
+            "i" | "info" => {
+                for stat in &self.breakpoints {
+                    println!("breakpoint {}", self.describe(*stat));
+                }
+                for addr in &self.watchpoints {
+                    println!("watchpoint [0x{addr:x}]");
+                }
+            }
+            "p" | "print" => match *args.first().ok_or("expected something to print")? {
+                "acc" => println!("acc = 0x{:x}", self.machine.accumulator),
+                "pc" => println!("pc = {}", self.machine.program_counter),
+                "carry" => println!("carry = {}", self.machine.carry),
+                raw => {
+                    let range = parse_memory(raw).ok_or("invalid register or address")?;
+                    for addr in range {
+                        let value = self.read(addr).ok_or("address outside of memory")?;
+                        println!("[0x{addr:x}] = 0x{value:x}");
+                    }
+                }
+            },
+            "l" | "list" => println!("{}", self.describe(self.machine.program_counter)),
+            "h" | "help" => println!("{HELP}"),
+            _ => return Err(format!("unknown command `{command}`, try `help`")),
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the debugger on stdin/stdout until the user quits.
+///
+/// `info` gives source positions for a program disassembled from an image.
+pub fn run(
+    mut machine: TickTalk<'_, Vec<DoubleWord>>,
+    sources: &SourceMap,
+    info: Option<&DebugInfo>,
+) {
+    machine.enable_history(HISTORY_LIMIT);
+    let mut debugger = Debugger {
+        program: machine.program,
+        machine,
+        sources,
+        info,
+        breakpoints: BTreeSet::new(),
+        watchpoints: BTreeSet::new(),
+    };
+    println!("{}", debugger.describe(0));
+
+    let mut stdin = std::io::stdin().lock();
+    let mut last = String::new();
+    loop {
+        print!("(ppr) ");
+        std::io::stdout().flush().expect("couldn't write to stdout");
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).expect("couldn't read stdin") == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            line.clone_from(&last);
+        } else {
+            last.clone_from(&line);
+        }
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        if matches!(command, "q" | "quit") {
+            break;
+        }
+        let args: Vec<_> = words.collect();
+        if let Err(e) = debugger.run_command(command, &args) {
+            println!("error: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const SOURCE: &str = "start: 10\nloop: ADD [0x10]\nSTORE [0x10]\nJUMP :loop";
+
+    fn debugger<'a>(program: &'a Program, sources: &'a SourceMap) -> Debugger<'a> {
+        let mut machine = TickTalk::new(program, &peppermint::target::TargetSpec::default());
+        machine.enable_history(HISTORY_LIMIT);
+        Debugger {
+            program,
+            machine,
+            sources,
+            info: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    #[test_case("[0x10]" => Some(0x10..=0x10))]
+    #[test_case("[0x10..0x12]" => Some(0x10..=0x12); "range")]
+    #[test_case("0x10" => None; "no brackets")]
+    #[test_case("[0x10" => None; "unclosed")]
+    #[test_case("[0x10000]" => None; "too large")]
+    fn memory(raw: &str) -> Option<RangeInclusive<Address>> {
+        parse_memory(raw)
+    }
+
+    #[test_case("loop" => Some(2))]
+    #[test_case("loop:" => Some(2); "trailing colon")]
+    #[test_case("1" => Some(1); "statement number")]
+    #[test_case("7" => None; "past the end")]
+    #[test_case("nowhere" => None)]
+    fn location(raw: &str) -> Option<usize> {
+        let program = Program::parse_source(SOURCE).expect("parse error");
+        let sources = SourceMap::new("test.ppr", SOURCE);
+        let debugger = debugger(&program, &sources);
+        debugger.location(raw)
+    }
+
+    #[test_case("s 3" => Ok(3); "step")]
+    #[test_case("s, n" => Ok(3); "next skips labels")]
+    #[test_case("b loop, c, c" => Ok(2); "continue to breakpoint")]
+    #[test_case("w [0x10], c" => Ok(5); "continue to watchpoint")]
+    #[test_case("s 4, back 2" => Ok(2); "back")]
+    #[test_case("s 5, rewind loop" => Ok(2); "rewind")]
+    #[test_case("s x" => Err("invalid step count".to_owned()); "bad step count")]
+    #[test_case("b nowhere" => Err("no such label or statement".to_owned()); "bad breakpoint")]
+    #[test_case("p [0x10" => Err("invalid register or address".to_owned()); "bad print")]
+    #[test_case("frobnicate" => Err("unknown command `frobnicate`, try `help`".to_owned()); "unknown")]
+    fn commands(commands: &str) -> Result<usize, String> {
+        let program = Program::parse_source(SOURCE).expect("parse error");
+        let sources = SourceMap::new("test.ppr", SOURCE);
+        let mut debugger = debugger(&program, &sources);
+        for line in commands.split(", ") {
+            let words: Vec<_> = line.split_whitespace().collect();
+            debugger.run_command(words[0], &words[1..])?;
+        }
+        Ok(debugger.machine.program_counter)
+    }
+
+    #[test_case(1 => "1: line 1 | start: 10")]
+    #[test_case(3 => "3: line 2 | loop: ADD [0x10]")]
+    #[test_case(6 => "6: <end of program>")]
+    fn describe(stat: usize) -> String {
+        let program = Program::parse_source(SOURCE).expect("parse error");
+        let sources = SourceMap::new("test.ppr", SOURCE);
+        let debugger = debugger(&program, &sources);
+        debugger.describe(stat)
+    }
+
+    #[test_case(0 => "0: Label(\"start\")")]
+    #[test_case(1 => "1: line 1 | Literal(10)")]
+    #[test_case(3 => "3: line 2 | InstrLine(Add(16))")]
+    fn describe_disassembled(stat: usize) -> String {
+        let program = Program::parse_source(SOURCE).expect("parse error");
+        let info = DebugInfo::new(&program, SOURCE, "test.ppr");
+        let target = peppermint::target::TargetSpec::default();
+        let image = peppermint::assemble::to_bytes(
+            &peppermint::assemble::assemble(&program, &target).expect("assembly error"),
+        );
+        let disassembled =
+            peppermint::disasm::disassemble_with_info(&image, &info).expect("disassembly error");
+        let sources = SourceMap::default();
+        let mut debugger = debugger(&disassembled, &sources);
+        debugger.info = Some(&info);
+        debugger.describe(stat)
+    }
+}
@@ -1,9 +1,14 @@
 use std::{
-    io::Read,
+    io::{Read, Write},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
 };
 
 use clap::Parser;
+use peppermint::{parse_int, source::SourceMap, target::TargetSpec};
+
+mod debug;
+mod test_runner;
 
 #[derive(Parser)]
 struct Opt {
@@ -13,6 +18,15 @@ struct Opt {
     /// File to read assembly from.
     #[clap(short, long, default_value = "/dev/stdin")]
     file: PathBuf,
+
+    /// TOML file describing the target machine, defaults to the current Tick Talk.
+    #[clap(short, long)]
+    target: Option<PathBuf>,
+
+    /// Directory to search for `.include`d files, after the including file's own directory.
+    /// Can be given several times, and directories are searched in order.
+    #[clap(short = 'I', long = "include", global = true)]
+    includes: Vec<PathBuf>,
 }
 
 #[derive(clap::Subcommand)]
@@ -20,51 +34,567 @@ enum Command {
     /// Parse the input file into an AST.
     Parse {},
     /// Assemble the input file into raw machine code.
-    Assemble { output_file: PathBuf },
+    Assemble {
+        output_file: PathBuf,
+        /// File to write JSON debug info to, mapping the machine code back to source.
+        #[arg(long)]
+        debug_info: Option<PathBuf>,
+        /// Write a JSON object which can import labels from other objects, for `link`.
+        #[arg(long, conflicts_with_all = ["debug_info", "memory_image"])]
+        object: bool,
+        /// File to write the initial values of `.word`s to as Intel HEX, which the machine code
+        /// doesn't contain, for `simulate --memory-init`.
+        #[arg(long)]
+        memory_image: Option<PathBuf>,
+    },
+    /// Link objects from `assemble --object` into raw machine code, ignoring the input file.
+    /// Execution starts at the first object.
+    Link {
+        /// Objects to link, in the order to place them.
+        #[arg(required = true)]
+        objects: Vec<PathBuf>,
+        /// File to write the machine code to.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Disassemble raw machine code in the input file back into source.
+    Disassemble {
+        /// JSON debug info from `assemble`, to restore label names and annotate source lines.
+        #[arg(long)]
+        debug_info: Option<PathBuf>,
+    },
+    /// Step through the input file interactively.
+    Debug {
+        /// Run the input file as raw machine code from `assemble`, with its JSON debug info to
+        /// show source positions.
+        #[arg(long)]
+        debug_info: Option<PathBuf>,
+    },
+    /// Run every `.ppr` file with `; expect` comments as a test, ignoring the input file.
+    Test {
+        /// Test files, or directories to search for them.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// File to write a JUnit XML report to.
+        #[arg(long)]
+        junit: Option<PathBuf>,
+    },
+    /// Format the input file into the canonical layout.
+    Fmt {
+        /// Don't output anything, exit with an error if the file isn't formatted.
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+        /// Write the formatted source back to the input file instead of stdout.
+        #[arg(short, long)]
+        write: bool,
+    },
     /// Simulate a file.
     Simulate {
-        /// Addresses to read from the memory at the end of execution.
+        /// Run the input file as raw machine code from `assemble`, with its JSON debug info to
+        /// show source positions.
+        #[arg(long)]
+        debug_info: Option<PathBuf>,
+        /// Addresses to read from the memory at the end of execution, in decimal, hex or binary.
+        #[arg(value_parser = parse_address)]
         addresses: Vec<peppermint::Address>,
-        /// Number of memory words to allocate
-        #[arg(short, long, default_value_t = 128_000)]
-        memory_size: usize,
+        /// Number of memory words to allocate, overriding the target
+        #[arg(short, long)]
+        memory_size: Option<usize>,
+        /// File to record an execution trace to, as JSON lines if it ends in `.jsonl` or binary
+        /// otherwise
+        #[arg(long)]
+        trace: Option<PathBuf>,
+        /// Stop with an error if the program doesn't halt within this many statements
+        #[arg(long)]
+        max_steps: Option<usize>,
+        /// Stop with an error if the program is stuck in a loop which repeats exactly
+        #[arg(long)]
+        detect_loops: bool,
+        /// Attach a memory-mapped device, as `KIND@ADDR`.
+        /// KIND is `console`, `input`, `input:FILE`, `timer` or `leds`
+        #[arg(short, long = "device", value_parser = parse_device)]
+        devices: Vec<DeviceArg>,
+        /// File to load initial memory contents from.
+        /// Read as Intel HEX if it ends in `.hex`, `address: value` lines if it ends in `.txt`,
+        /// or raw big-endian words otherwise
+        #[arg(long)]
+        memory_init: Option<PathBuf>,
+        /// Print the final machine state to stdout in a machine-readable format
+        #[arg(long, value_enum)]
+        dump_state: Option<DumpFormat>,
+        /// Range of memory to include in the state dump, as `START..END` inclusive
+        #[arg(long, value_parser = parse_range, requires = "dump_state")]
+        dump_range: Option<RangeInclusive<peppermint::Address>>,
+        /// Only include non-zero memory cells in the state dump
+        #[arg(long, requires = "dump_state")]
+        non_zero: bool,
     },
 }
 
+/// Format to dump the final machine state in.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum DumpFormat {
+    Json,
+    Yaml,
+    /// Memory only, as Intel HEX.
+    Hex,
+}
+
+/// Kind of memory-mapped device to attach.
+#[derive(Clone)]
+enum DeviceKind {
+    Console,
+    Input(Option<PathBuf>),
+    Timer,
+    Leds,
+}
+
+/// Device given on the command line.
+#[derive(Clone)]
+struct DeviceArg {
+    kind: DeviceKind,
+    address: peppermint::Address,
+}
+
+impl DeviceArg {
+    fn attach(self, bus: &mut peppermint_simulate::device::Bus) {
+        use peppermint_simulate::device::{Console, InputPort, Leds, Timer};
+        // the devices are only used by the program, so their handles aren't needed
+        let result = match self.kind {
+            DeviceKind::Console => bus
+                .attach(self.address, Console::new(std::io::stdout()))
+                .map(drop),
+            DeviceKind::Input(None) => bus
+                .attach(self.address, InputPort::new(std::io::stdin()))
+                .map(drop),
+            DeviceKind::Input(Some(path)) => {
+                let file = std::fs::File::open(path).expect("couldn't open input file");
+                bus.attach(self.address, InputPort::new(std::io::BufReader::new(file)))
+                    .map(drop)
+            }
+            DeviceKind::Timer => bus.attach(self.address, Timer::default()).map(drop),
+            DeviceKind::Leds => bus
+                .attach(self.address, Leds::new(std::io::stdout()))
+                .map(drop),
+        };
+        result
+            .map_err(|e| e.to_string())
+            .expect("couldn't attach device");
+    }
+}
+
+fn parse_device(raw: &str) -> Result<DeviceArg, String> {
+    let (kind, address) = raw.split_once('@').ok_or("expected KIND@ADDR")?;
+    let address = parse_int(address).ok_or_else(|| format!("invalid address `{address}`"))?;
+    let kind = match kind.split_once(':') {
+        Some(("input", path)) => DeviceKind::Input(Some(path.into())),
+        None if kind == "console" => DeviceKind::Console,
+        None if kind == "input" => DeviceKind::Input(None),
+        None if kind == "timer" => DeviceKind::Timer,
+        None if kind == "leds" => DeviceKind::Leds,
+        _ => return Err(format!("unknown device `{kind}`")),
+    };
+    Ok(DeviceArg { kind, address })
+}
+
+fn parse_address(raw: &str) -> Result<peppermint::Address, String> {
+    parse_int(raw).ok_or_else(|| format!("invalid address `{raw}`"))
+}
+
+fn parse_range(raw: &str) -> Result<RangeInclusive<peppermint::Address>, String> {
+    let (start, end) = raw.split_once("..").ok_or("expected START..END")?;
+    let parse = |n| parse_int(n).ok_or_else(|| format!("invalid address `{n}`"));
+    Ok(parse(start)?..=parse(end)?)
+}
+
 fn main() {
     let opt = Opt::parse();
+    let target = opt.target.as_deref().map(load_target).unwrap_or_default();
+    // tests bring their own files, so don't wait on stdin
+    if let Command::Test { paths, junit } = &opt.command {
+        let passed = test_runner::run(paths, &opt.includes, &target, junit.as_deref());
+        std::process::exit(i32::from(!passed));
+    }
+    if let Command::Link { objects, output } = &opt.command {
+        link(objects, output);
+        return;
+    }
     let content = get_file_content(&opt.file);
-    let program = peppermint::Program::parse_source(&content)
-        .map_err(|e| e.spans_to_source(&content).to_string())
-        .unwrap();
 
     match opt.command {
         Command::Parse {} => {
+            let (program, _) = parse_program(&content, &opt.file, &opt.includes, &target);
             println!("{:?}", program);
         }
         Command::Simulate {
+            debug_info,
             addresses,
             memory_size,
+            trace,
+            max_steps,
+            detect_loops,
+            devices,
+            memory_init,
+            dump_state,
+            dump_range,
+            non_zero,
         } => {
-            let mut machine = peppermint_simulate::TickTalk::new(&program, memory_size);
+            if let Some(addr) = addresses.iter().find(|&&a| a > target.max_address()) {
+                eprintln!(
+                    "error: address 0x{addr:x} is past the largest address on the target, 0x{:x}",
+                    target.max_address()
+                );
+                std::process::exit(1);
+            }
+            let info = debug_info.as_deref().map(load_debug_info);
+            let (program, sources) = match &info {
+                Some(info) => disassemble_program(&content, info),
+                None => parse_program(&content, &opt.file, &opt.includes, &target),
+            };
+            let mut target = target;
+            if let Some(memory_size) = memory_size {
+                target.memory_size = memory_size;
+            }
+            let mut machine = peppermint_simulate::TickTalk::new(&program, &target);
+            if trace.is_some() {
+                machine.trace = Some(Vec::new());
+            }
+            machine.detect_loops = detect_loops;
+            if let Some(path) = memory_init {
+                machine
+                    .load_memory(load_image(&path))
+                    .map_err(|e| e.to_string())
+                    .expect("couldn't load memory image");
+            }
+            for device in devices {
+                device.attach(&mut machine.devices);
+            }
 
-            machine.run_to_completion().expect("simulation error");
+            let result = match max_steps {
+                Some(limit) => machine.run_with_limit(limit),
+                None => machine.run_to_completion(),
+            };
+            // write the trace and state even if simulation failed, they're most useful then
+            if let (Some(path), Some(entries)) = (trace, &machine.trace) {
+                write_trace(&path, entries);
+            }
+            if let Some(format) = dump_state {
+                let range = dump_range.unwrap_or(0..=peppermint::Address::MAX);
+                dump(&machine.snapshot(range, non_zero), format);
+            }
+            if let Err(error) = result {
+                eprintln!(
+                    "{}",
+                    render_simulation_error(&error, &machine, &sources, info.as_ref())
+                );
+                std::process::exit(1);
+            }
             for addr in addresses {
-                println!("addr [0x{addr:x}]: 0x{:x}", machine.memory[addr as usize]);
+                let value = or_exit(machine.memory.get(addr as usize).ok_or_else(|| {
+                    format!(
+                        "address 0x{addr:x} is outside of memory, which has {} words",
+                        machine.memory.len()
+                    )
+                }));
+                println!("addr [0x{addr:x}]: 0x{value:x}");
             }
         }
-        Command::Assemble { output_file: _ } => todo!("assembler not implemented yet"),
+        Command::Assemble {
+            output_file,
+            object: true,
+            ..
+        } => {
+            let content = std::str::from_utf8(&content).expect("input file isn't valid UTF-8");
+            let name = opt.file.display().to_string();
+            let program =
+                match peppermint::Program::parse_object(&name, content, &opt.includes, &target) {
+                    (_, Ok(program)) => program,
+                    (sources, Err(errors)) => {
+                        report_errors(&errors, &sources);
+                        std::process::exit(1);
+                    }
+                };
+            let object = or_exit(peppermint::object::Object::assemble(
+                &program, name, &target,
+            ));
+            let json = serde_json::to_string(&object).expect("couldn't serialise object");
+            std::fs::write(output_file, json).expect("couldn't write output file");
+        }
+        Command::Assemble {
+            output_file,
+            debug_info,
+            object: false,
+            memory_image,
+        } => {
+            let (program, sources) = parse_program(&content, &opt.file, &opt.includes, &target);
+            let words = or_exit(peppermint::assemble::assemble(&program, &target));
+            std::fs::write(output_file, peppermint::assemble::to_bytes(&words))
+                .expect("couldn't write output file");
+            match memory_image {
+                Some(path) => std::fs::write(
+                    path,
+                    peppermint_simulate::image::to_intel_hex(
+                        program.initial_memory().iter().copied(),
+                    ),
+                )
+                .expect("couldn't write memory image"),
+                None if !program.initial_memory().is_empty() => eprintln!(
+                    "warning: the machine code doesn't contain the initial values of `.word`s, \
+                     write them with --memory-image"
+                ),
+                None => {}
+            }
+            if let Some(path) = debug_info {
+                let info = peppermint::debug_info::DebugInfo::from_sources(&program, &sources);
+                let json = serde_json::to_string(&info).expect("couldn't serialise debug info");
+                std::fs::write(path, json).expect("couldn't write debug info");
+            }
+        }
+        Command::Disassemble { debug_info: None } => {
+            let program = or_exit(peppermint::Program::from_machine_code(&content));
+            print!("{program}");
+        }
+        Command::Disassemble {
+            debug_info: Some(path),
+        } => {
+            let info = load_debug_info(&path);
+            let program = or_exit(peppermint::disasm::disassemble_with_info(&content, &info));
+            print_annotated(&program, &info);
+        }
+        Command::Debug { debug_info } => {
+            if matches!(opt.file.to_str(), Some("-" | "/dev/stdin")) {
+                eprintln!("the debugger reads commands from stdin, pass a program with --file");
+                std::process::exit(1);
+            }
+            let info = debug_info.as_deref().map(load_debug_info);
+            let (program, sources) = match &info {
+                Some(info) => disassemble_program(&content, info),
+                None => parse_program(&content, &opt.file, &opt.includes, &target),
+            };
+            debug::run(
+                peppermint_simulate::TickTalk::new(&program, &target),
+                &sources,
+                info.as_ref(),
+            );
+        }
+        Command::Test { .. } | Command::Link { .. } => {
+            unreachable!("handled before reading the input file")
+        }
+        Command::Fmt { check, write } => {
+            let content = std::str::from_utf8(&content).expect("input file isn't valid UTF-8");
+            let formatted = match peppermint::format::format_source(content) {
+                Ok(formatted) => formatted,
+                Err(error) => {
+                    let sources = SourceMap::new(opt.file.display().to_string(), content);
+                    report_errors(&[error], &sources);
+                    std::process::exit(1);
+                }
+            };
+
+            if check {
+                if formatted != content {
+                    eprintln!("{} is not formatted", opt.file.display());
+                    std::process::exit(1);
+                }
+            } else if write {
+                std::fs::write(&opt.file, formatted).expect("couldn't write file");
+            } else {
+                print!("{formatted}");
+            }
+        }
+    }
+}
+
+/// Link object files into an image, reporting every linking error.
+fn link(paths: &[PathBuf], output: &Path) {
+    let objects: Vec<peppermint::object::Object> = paths
+        .iter()
+        .map(|path| {
+            serde_json::from_slice(&std::fs::read(path).expect("couldn't read object"))
+                .map_err(|e| e.to_string())
+                .expect("invalid object")
+        })
+        .collect();
+    match peppermint::object::link(&objects) {
+        Ok(words) => std::fs::write(output, peppermint::assemble::to_bytes(&words))
+            .expect("couldn't write output file"),
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {error}");
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print a disassembled program with the source position of each statement as a comment.
+fn print_annotated(program: &peppermint::Program, info: &peppermint::debug_info::DebugInfo) {
+    let words = peppermint::assemble::word_indices(program);
+    // the program is displayed one statement per line
+    let listing = program.to_string();
+    for ((line, statement), word) in listing.lines().zip(program.statements()).zip(words) {
+        match info.location_of(word) {
+            Some(loc) if !matches!(statement, peppermint::Statement::Label(_)) => {
+                let file = loc.file.as_ref().unwrap_or(&info.file);
+                println!("{line:<24}; {file}:{}:{}", loc.line, loc.column);
+            }
+            _ => println!("{line}"),
+        }
+    }
+}
+
+fn dump(snapshot: &peppermint_simulate::Snapshot, format: DumpFormat) {
+    match format {
+        DumpFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(snapshot).expect("couldn't serialise state")
+        ),
+        DumpFormat::Yaml => print!(
+            "{}",
+            serde_yaml::to_string(snapshot).expect("couldn't serialise state")
+        ),
+        DumpFormat::Hex => print!(
+            "{}",
+            peppermint_simulate::image::to_intel_hex(snapshot.memory.clone())
+        ),
+    }
+}
+
+fn write_trace(path: &Path, entries: &[peppermint_simulate::trace::TraceEntry]) {
+    let file = std::fs::File::create(path).expect("couldn't create trace file");
+    let mut out = std::io::BufWriter::new(file);
+    if path.extension().is_some_and(|ext| ext == "jsonl") {
+        for entry in entries {
+            serde_json::to_writer(&mut out, entry).expect("couldn't write trace file");
+            writeln!(out).expect("couldn't write trace file");
+        }
+    } else {
+        peppermint_simulate::trace::write_binary(&mut out, entries)
+            .expect("couldn't write trace file");
+    }
+    out.flush().expect("couldn't write trace file");
+}
+
+fn load_image(path: &Path) -> peppermint_simulate::image::Cells {
+    use peppermint_simulate::image::Format;
+    let format = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("hex") => Format::IntelHex,
+        Some("txt") => Format::Text,
+        _ => Format::Binary,
+    };
+    let content = std::fs::read(path).expect("couldn't read memory image");
+    peppermint_simulate::image::parse(&content, format)
+        .map_err(|e| e.to_string())
+        .expect("invalid memory image")
+}
+
+fn load_target(path: &Path) -> TargetSpec {
+    let content = std::fs::read_to_string(path).expect("couldn't read target file");
+    toml::from_str(&content)
+        .map_err(|e| e.to_string())
+        .expect("invalid target file")
+}
+
+/// Parse the input file and everything it includes, returning the program with its sources.
+fn parse_program(
+    content: &[u8],
+    file: &Path,
+    includes: &[PathBuf],
+    target: &TargetSpec,
+) -> (peppermint::Program, SourceMap) {
+    let content = std::str::from_utf8(content).expect("input file isn't valid UTF-8");
+    let name = file.display().to_string();
+    match peppermint::Program::parse_with_includes(&name, content, includes, target) {
+        (sources, Ok(program)) => (program, sources),
+        (sources, Err(errors)) => {
+            report_errors(&errors, &sources);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Disassemble raw machine code with its debug info, which has no source to go with it.
+fn disassemble_program(
+    content: &[u8],
+    info: &peppermint::debug_info::DebugInfo,
+) -> (peppermint::Program, SourceMap) {
+    let program = or_exit(peppermint::disasm::disassemble_with_info(content, info));
+    (program, SourceMap::default())
+}
+
+fn load_debug_info(path: &Path) -> peppermint::debug_info::DebugInfo {
+    serde_json::from_slice(&std::fs::read(path).expect("couldn't read debug info"))
+        .map_err(|e| e.to_string())
+        .expect("invalid debug info")
+}
+
+/// Render a simulation error against the source of the statement which caused it.
+///
+/// A program disassembled from an image has no source, so the error gives the position from
+/// `info` instead.
+fn render_simulation_error<M: std::ops::DerefMut<Target = [peppermint::DoubleWord]>>(
+    error: &peppermint_simulate::Error,
+    machine: &peppermint_simulate::TickTalk<'_, M>,
+    sources: &SourceMap,
+    info: Option<&peppermint::debug_info::DebugInfo>,
+) -> String {
+    use peppermint::diagnostic::{Diagnostic, Label};
+    use peppermint_simulate::Error;
+
+    let stat = machine.program_counter;
+    let (span, message) = match error {
+        Error::AccessOutOfBounds => (machine.program.operand_span_of(stat), "outside of memory"),
+        Error::Overflow { .. } => (machine.program.span_of(stat), "overflowed here"),
+        Error::StepLimitExceeded { .. } => (machine.program.span_of(stat), "still running here"),
+        Error::InfiniteLoop { .. } => (machine.program.span_of(stat), "this loop never exits"),
+        Error::UnsupportedInstruction { .. } => (
+            machine.program.span_of(stat),
+            "not in the base instruction set",
+        ),
+        Error::DeviceOverlap { .. } => (None, ""),
+    };
+    let location = info.and_then(|info| {
+        let loc = machine.source_location(info)?;
+        let file = loc.file.as_ref().unwrap_or(&info.file);
+        Some(format!(" at {file}:{}:{}", loc.line, loc.column))
+    });
+    Diagnostic {
+        message: format!("{error}{}", location.unwrap_or_default()),
+        labels: span
+            .map(|span| Label {
+                span,
+                message: message.to_owned(),
+                primary: true,
+            })
+            .into_iter()
+            .collect(),
+        help: None,
+    }
+    .render_sources(sources)
+}
+
+/// Unwrap the result of assembling or disassembling, or report the error and exit.
+fn or_exit<T>(result: Result<T, impl std::fmt::Display>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("error: {error}");
+        std::process::exit(1);
+    })
+}
+
+fn report_errors(errors: &[peppermint::error::Error], sources: &SourceMap) {
+    for error in errors {
+        eprintln!("{}", error.render_sources(sources));
     }
 }
 
-fn get_file_content(input: &Path) -> String {
+fn get_file_content(input: &Path) -> Vec<u8> {
     if input.to_str() == Some("-") {
-        let mut buf = String::new();
+        let mut buf = Vec::new();
         let mut stdin = std::io::stdin();
-        stdin.read_to_string(&mut buf).expect("couldn't read stdin");
+        stdin.read_to_end(&mut buf).expect("couldn't read stdin");
 
         buf
     } else {
-        std::fs::read_to_string(input).expect("couldn't read file")
+        std::fs::read(input).expect("couldn't read file")
     }
 }
@@ -0,0 +1,308 @@
+//! Golden-file test runner.
+//!
+//! A test is a `.ppr` file with expectations in its comments:
+//! ```text
+//! ; expect [0x21] = 15
+//! ; expect acc = 3
+//! ; max-steps 1000
+//! ```
+//! The program is run to completion and every expectation is checked against the final state.
+//! Files without any `expect` comments aren't tests, and are skipped.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use peppermint::{parse_int, target::TargetSpec, Address, DoubleWord, Program};
+use peppermint_simulate::TickTalk;
+
+/// Step limit for tests which don't set their own, so a broken loop can't hang the suite.
+const DEFAULT_MAX_STEPS: usize = 1_000_000;
+
+/// Value a test expects at the end of the run.
+enum Expectation {
+    Memory(Address, DoubleWord),
+    Accumulator(DoubleWord),
+}
+
+/// Expectations parsed from a test file.
+struct Spec {
+    expectations: Vec<Expectation>,
+    max_steps: usize,
+}
+
+/// Outcome of one test file.
+struct Outcome {
+    path: PathBuf,
+    duration: Duration,
+    /// Reasons the test failed, empty if it passed.
+    failures: Vec<String>,
+}
+
+/// Parse the expectations from a file's comments.
+///
+/// Returns [`None`] if the file isn't a test.
+fn parse_spec(source: &str) -> Result<Option<Spec>, String> {
+    let mut expectations = Vec::new();
+    let mut max_steps = DEFAULT_MAX_STEPS;
+    for span in peppermint::comments(source) {
+        let (line, _) = peppermint::diagnostic::line_col(source, span.start);
+        let text = source[span][1..].trim();
+        let malformed = || format!("line {line}: malformed directive `{text}`");
+
+        if let Some(rest) = text.strip_prefix("expect ") {
+            let (lhs, rhs) = rest.split_once('=').ok_or_else(malformed)?;
+            let value = parse_int(rhs.trim()).ok_or_else(malformed)?;
+            let lhs = lhs.trim();
+            expectations.push(if lhs == "acc" {
+                Expectation::Accumulator(value)
+            } else {
+                let addr = lhs
+                    .strip_prefix('[')
+                    .and_then(|a| a.strip_suffix(']'))
+                    .and_then(parse_int)
+                    .ok_or_else(malformed)?;
+                Expectation::Memory(addr, value)
+            });
+        } else if let Some(rest) = text.strip_prefix("max-steps ") {
+            max_steps = parse_int(rest.trim()).ok_or_else(malformed)?;
+        }
+    }
+
+    Ok((!expectations.is_empty()).then_some(Spec {
+        expectations,
+        max_steps,
+    }))
+}
+
+/// Run one test, returning [`None`] if the file isn't a test.
+fn run_file(path: &Path, includes: &[PathBuf], target: &TargetSpec) -> Option<Outcome> {
+    let start = Instant::now();
+    let outcome = |failures| {
+        Some(Outcome {
+            path: path.to_owned(),
+            duration: start.elapsed(),
+            failures,
+        })
+    };
+
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => return outcome(vec![format!("couldn't read file: {e}")]),
+    };
+    let spec = match parse_spec(&source) {
+        Ok(spec) => spec?,
+        Err(e) => return outcome(vec![e]),
+    };
+    let (sources, result) =
+        Program::parse_with_includes(&path.display().to_string(), &source, includes, target);
+    let program = match result {
+        Ok(program) => program,
+        Err(errors) => {
+            return outcome(errors.iter().map(|e| e.render_sources(&sources)).collect());
+        }
+    };
+
+    let mut machine = TickTalk::new(&program, target);
+    if let Err(e) = machine.run_with_limit(spec.max_steps) {
+        return outcome(vec![crate::render_simulation_error(
+            &e, &machine, &sources, None,
+        )]);
+    }
+    let failures = spec
+        .expectations
+        .iter()
+        .filter_map(|expectation| {
+            let (name, expected, actual) = match *expectation {
+                Expectation::Memory(addr, expected) => (
+                    format!("[0x{addr:x}]"),
+                    expected,
+                    machine.memory.get(addr as usize).copied(),
+                ),
+                Expectation::Accumulator(expected) => {
+                    ("acc".to_owned(), expected, Some(machine.accumulator))
+                }
+            };
+            match actual {
+                Some(actual) if actual == expected => None,
+                Some(actual) => Some(format!("{name}: expected {expected}, got {actual}")),
+                None => Some(format!("{name}: address outside of memory")),
+            }
+        })
+        .collect();
+    outcome(failures)
+}
+
+/// Find every `.ppr` file in `paths`, searching directories recursively.
+fn discover(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<_> = std::fs::read_dir(path)
+                .expect("couldn't read directory")
+                .map(|entry| entry.expect("couldn't read directory").path())
+                .filter(|p| p.is_dir() || p.extension().is_some_and(|ext| ext == "ppr"))
+                .collect();
+            entries.sort();
+            files.extend(discover(&entries));
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Escape text for use in XML attributes and content.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Render outcomes as a JUnit XML report.
+fn junit(outcomes: &[Outcome]) -> String {
+    let failed = outcomes.iter().filter(|o| !o.failures.is_empty()).count();
+    let total: Duration = outcomes.iter().map(|o| o.duration).sum();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"peppermint\" tests=\"{}\" failures=\"{failed}\" time=\"{:.3}\">",
+        outcomes.len(),
+        total.as_secs_f64(),
+    );
+    for outcome in outcomes {
+        let _ = write!(
+            xml,
+            "  <testcase name=\"{}\" time=\"{:.3}\"",
+            escape_xml(&outcome.path.display().to_string()),
+            outcome.duration.as_secs_f64(),
+        );
+        if outcome.failures.is_empty() {
+            xml.push_str("/>\n");
+        } else {
+            let _ = writeln!(
+                xml,
+                ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                escape_xml(&outcome.failures[0]),
+                escape_xml(&outcome.failures.join("\n")),
+            );
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+/// Run every test in `paths`, printing results and optionally writing a JUnit report.
+///
+/// Files included by tests are searched for in `includes`.
+/// Returns whether every test passed.
+pub fn run(
+    paths: &[PathBuf],
+    includes: &[PathBuf],
+    target: &TargetSpec,
+    junit_path: Option<&Path>,
+) -> bool {
+    let outcomes: Vec<_> = discover(paths)
+        .iter()
+        .filter_map(|path| run_file(path, includes, target))
+        .collect();
+
+    for outcome in &outcomes {
+        if outcome.failures.is_empty() {
+            println!("PASS {}", outcome.path.display());
+        } else {
+            println!("FAIL {}", outcome.path.display());
+            for failure in &outcome.failures {
+                for line in failure.lines() {
+                    println!("    {line}");
+                }
+            }
+        }
+    }
+    let failed = outcomes.iter().filter(|o| !o.failures.is_empty()).count();
+    println!("\n{} passed, {failed} failed", outcomes.len() - failed);
+
+    if let Some(path) = junit_path {
+        std::fs::write(path, junit(&outcomes)).expect("couldn't write JUnit report");
+    }
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// Render a spec compactly so test cases can compare it.
+    fn describe(spec: &Spec) -> String {
+        let mut out = format!("max-steps {}", spec.max_steps);
+        for expectation in &spec.expectations {
+            match expectation {
+                Expectation::Memory(addr, value) => write!(out, ", [{addr}] = {value}"),
+                Expectation::Accumulator(value) => write!(out, ", acc = {value}"),
+            }
+            .unwrap();
+        }
+        out
+    }
+
+    #[test_case("; expect [0x21] = 15" => Ok(Some("max-steps 1000000, [33] = 15".to_owned())); "memory")]
+    #[test_case("; expect acc = 0b11\n; max-steps 10" => Ok(Some("max-steps 10, acc = 3".to_owned())); "accumulator")]
+    #[test_case("LOAD 1 ; expect acc = 1" => Ok(Some("max-steps 1000000, acc = 1".to_owned())); "trailing comment")]
+    #[test_case("; just a comment\nLOAD 1" => Ok(None); "not a test")]
+    #[test_case("; max-steps 10" => Ok(None); "no expectations")]
+    #[test_case("; expect acc 3" => Err("line 1: malformed directive `expect acc 3`".to_owned()); "missing equals")]
+    #[test_case("; expect acc = x" => Err("line 1: malformed directive `expect acc = x`".to_owned()); "bad value")]
+    #[test_case("; expect acc = 0x10000" => Err("line 1: malformed directive `expect acc = 0x10000`".to_owned()); "value too large")]
+    #[test_case("\n; expect 0x21 = 1" => Err("line 2: malformed directive `expect 0x21 = 1`".to_owned()); "missing brackets")]
+    #[test_case("; expect acc = 1\n; max-steps lots" => Err("line 2: malformed directive `max-steps lots`".to_owned()); "bad max steps")]
+    fn spec(source: &str) -> Result<Option<String>, String> {
+        parse_spec(source).map(|spec| spec.as_ref().map(describe))
+    }
+
+    #[test_case("plain" => "plain")]
+    #[test_case("<&\"'>" => "&lt;&amp;&quot;&apos;&gt;"; "special")]
+    #[test_case("&lt;" => "&amp;lt;"; "already escaped")]
+    fn escape(text: &str) -> String {
+        escape_xml(text)
+    }
+
+    fn outcome(path: &str, failures: &[&str]) -> Outcome {
+        Outcome {
+            path: path.into(),
+            duration: Duration::from_millis(1500),
+            failures: failures.iter().map(|&f| f.to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn junit_report() {
+        let outcomes = [
+            outcome("pass.ppr", &[]),
+            outcome(
+                "fail.ppr",
+                &["acc: expected 3, got 4", "[0x21]: expected 1, got 0"],
+            ),
+            outcome("<error>.ppr", &["couldn't read file: \"missing\""]),
+        ];
+        assert_eq!(
+            junit(&outcomes),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="peppermint" tests="3" failures="2" time="4.500">
+  <testcase name="pass.ppr" time="1.500"/>
+  <testcase name="fail.ppr" time="1.500">
+    <failure message="acc: expected 3, got 4">acc: expected 3, got 4
+[0x21]: expected 1, got 0</failure>
+  </testcase>
+  <testcase name="&lt;error&gt;.ppr" time="1.500">
+    <failure message="couldn&apos;t read file: &quot;missing&quot;">couldn&apos;t read file: &quot;missing&quot;</failure>
+  </testcase>
+</testsuite>
+"#
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Tick Talk machine code generation.
+//!
+//! Every non-label statement is encoded as one 16-bit word:
+//!
+//! | statement   | bit 15 | bits 14-11 | bits 10-0      |
+//! |-------------|--------|------------|----------------|
+//! | literal     | `0`    | literal value (15 bits)     ||
+//! | instruction | `1`    | opcode     | address/target |
+//!
+//! Jump targets are word indices into the image; label statements don't take up a word, so a jump
+//! to a label refers to the word of the statement that follows it.
+//! The opcode field is wider than the base instruction set needs to leave room for extensions;
+//! the [`InstructionSet::Extended`] instructions take opcodes 8 to 10.
+//! Operands are also limited by the [`TargetSpec`] being assembled for.
+
+use super::{
+    target::{InstructionSet, TargetSpec},
+    Address, DoubleWord, Instruction, Literal, Program, StatNum, Statement,
+};
+
+/// Bit which marks a word as an instruction rather than a literal.
+pub(crate) const INSTRUCTION_FLAG: DoubleWord = 1 << 15;
+/// Offset of the opcode field in an instruction word.
+pub(crate) const OPCODE_SHIFT: u32 = 11;
+/// Mask for the operand field of an instruction word.
+pub(crate) const OPERAND_MASK: DoubleWord = (1 << OPCODE_SHIFT) - 1;
+/// Mask for the value of a literal word.
+const LITERAL_MASK: DoubleWord = !INSTRUCTION_FLAG;
+
+/// Error in machine code generation.
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum Error {
+    /// Address doesn't fit in the address field.
+    #[error("address 0x{address:x} in statement {statement} is too large to encode")]
+    AddressOutOfRange {
+        /// Index of the offending statement.
+        statement: StatNum,
+        /// The address which is too large.
+        address: Address,
+    },
+    /// Literal doesn't fit in a literal word.
+    #[error("literal {literal} in statement {statement} is too large to encode")]
+    LiteralOutOfRange {
+        /// Index of the offending statement.
+        statement: StatNum,
+        /// The literal which is too large.
+        literal: Literal,
+    },
+    /// Jump target is beyond the reach of the operand field.
+    #[error("jump target in statement {statement} is too far into the program to encode")]
+    JumpOutOfRange {
+        /// Index of the offending statement.
+        statement: StatNum,
+    },
+    /// Instruction which isn't in the target's instruction set.
+    #[error("instruction in statement {statement} isn't supported by the target")]
+    UnsupportedInstruction {
+        /// Index of the offending statement.
+        statement: StatNum,
+    },
+    /// Label imported or exported by an object which isn't a label statement.
+    #[error("link target in statement {statement} is not a label")]
+    LinkTargetNotLabel {
+        /// Index of the offending statement.
+        statement: StatNum,
+    },
+}
+
+/// Numeric opcode of an instruction.
+pub(crate) fn opcode<L>(instruction: &Instruction<L>) -> DoubleWord {
+    match instruction {
+        Instruction::Load(_) => 0,
+        Instruction::And(_) => 1,
+        Instruction::Xor(_) => 2,
+        Instruction::Or(_) => 3,
+        Instruction::Add(_) => 4,
+        Instruction::Sub(_) => 5,
+        Instruction::Store(_) => 6,
+        Instruction::Jump(_) => 7,
+        Instruction::JumpZ(_) => 8,
+        Instruction::JumpN(_) => 9,
+        Instruction::Halt => 10,
+    }
+}
+
+/// Word index of every statement of a program in its assembled image.
+///
+/// Labels share the index of the following word, and there's one extra entry at the end for the
+/// index just past the end of the image.
+#[must_use]
+pub fn word_indices(program: &Program) -> Vec<usize> {
+    let statements = program.statements();
+    let mut word_indices = Vec::with_capacity(statements.len() + 1);
+    let mut next_word = 0;
+    for stat in statements {
+        word_indices.push(next_word);
+        if !matches!(stat, Statement::Label(_)) {
+            next_word += 1;
+        }
+    }
+    // a jump to a trailing label lands just past the end of the image
+    word_indices.push(next_word);
+    word_indices
+}
+
+/// Assemble a program into a list of machine words for `target`.
+///
+/// # Errors
+///
+/// Throws [`enum@Error`] if any operand of the program doesn't fit the encoding or the target.
+pub fn assemble(program: &Program, target: &TargetSpec) -> Result<Vec<DoubleWord>, Error> {
+    let statements = program.statements();
+    let max_address = target.max_address().min(OPERAND_MASK);
+    let max_literal = target.max_literal().min(LITERAL_MASK);
+
+    let word_indices = word_indices(program);
+
+    let mut words = Vec::with_capacity(word_indices[statements.len()]);
+    for (i, stat) in statements.iter().enumerate() {
+        match stat {
+            Statement::Label(_) => {}
+            Statement::Literal(literal) => {
+                if *literal > max_literal {
+                    return Err(Error::LiteralOutOfRange {
+                        statement: i,
+                        literal: *literal,
+                    });
+                }
+                words.push(*literal);
+            }
+            Statement::InstrLine(ins) => {
+                if ins.is_extended() && target.instruction_set == InstructionSet::Base {
+                    return Err(Error::UnsupportedInstruction { statement: i });
+                }
+                let operand = match ins {
+                    Instruction::Jump(target)
+                    | Instruction::JumpZ(target)
+                    | Instruction::JumpN(target) => DoubleWord::try_from(word_indices[*target])
+                        .ok()
+                        .filter(|t| *t <= OPERAND_MASK)
+                        .ok_or(Error::JumpOutOfRange { statement: i })?,
+                    Instruction::Load(address)
+                    | Instruction::And(address)
+                    | Instruction::Xor(address)
+                    | Instruction::Or(address)
+                    | Instruction::Add(address)
+                    | Instruction::Sub(address)
+                    | Instruction::Store(address) => {
+                        if *address > max_address {
+                            return Err(Error::AddressOutOfRange {
+                                statement: i,
+                                address: *address,
+                            });
+                        }
+                        *address
+                    }
+                    Instruction::Halt => 0,
+                };
+                words.push(INSTRUCTION_FLAG | (opcode(ins) << OPCODE_SHIFT) | operand);
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+/// Serialise machine words into a big-endian byte image.
+#[must_use]
+pub fn to_bytes(words: &[DoubleWord]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("10" => vec![10])]
+    #[test_case("LOAD [0x10]" => vec![0x8010])]
+    #[test_case("STORE [0x7f]" => vec![0xB07F])]
+    #[test_case("10 label: STORE [0x20] JUMP :label" => vec![10, 0xB020, 0xB801])]
+    #[test_case("JUMP :end 10 end:" => vec![0xB802, 10]; "jump to trailing label")]
+    fn assemble_source(input: &str) -> Vec<DoubleWord> {
+        let program = Program::parse_source(input).expect("parse error");
+        assemble(&program, &TargetSpec::default()).expect("assembly error")
+    }
+
+    #[test_case("a: JUMPZ :a JUMPN :a" => vec![0xC000, 0xC800])]
+    #[test_case("HALT" => vec![0xD000])]
+    fn assemble_extended(input: &str) -> Vec<DoubleWord> {
+        let target = TargetSpec {
+            instruction_set: InstructionSet::Extended,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source_for(input, &target).expect("parse error");
+        assemble(&program, &target).expect("assembly error")
+    }
+
+    #[test_case("LOAD [0x80]" => matches Error::AddressOutOfRange { statement: 0, address: 0x80 })]
+    #[test_case("10 0x8000" => matches Error::LiteralOutOfRange { statement: 1, .. })]
+    #[test_case("10 HALT" => matches Error::UnsupportedInstruction { statement: 1 })]
+    fn assemble_error(input: &str) -> Error {
+        // the parser would reject these for the real target
+        let wide = TargetSpec {
+            address_bits: 16,
+            literal_bits: 16,
+            instruction_set: InstructionSet::Extended,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source_for(input, &wide).expect("parse error");
+        assemble(&program, &TargetSpec::default()).expect_err("no error thrown")
+    }
+
+    #[test]
+    fn bytes_are_big_endian() {
+        assert_eq!(to_bytes(&[0x8010, 0x000A]), vec![0x80, 0x10, 0x00, 0x0A]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Debug information for assembled images.
+//!
+//! Assembly throws away everything which doesn't end up in machine code, so [`DebugInfo`] keeps a
+//! record of where each word came from in the source and which labels point at it.
+//! It's meant to be stored next to the image, and with the `serde` feature it can be serialised
+//! in any format serde supports.
+//!
+//! To name labels in a disassembled image, see [`crate::disasm::disassemble_with_info`].
+
+use super::{
+    assemble::word_indices, diagnostic::line_col, source::SourceMap, Program, StatNum, Statement,
+};
+
+/// Source position of one word of an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineEntry {
+    /// Index of the word in the image.
+    pub word: usize,
+    /// 1-based line of the statement in the source.
+    pub line: usize,
+    /// 1-based column of the statement in the source.
+    pub column: usize,
+    /// Name of the file the statement is in, if it was included rather than in the main file.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub file: Option<String>,
+}
+
+/// Label name and the word it points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Symbol {
+    /// Name of the label.
+    pub name: String,
+    /// Index of the word the label points at, which is the length of the image for a trailing
+    /// label.
+    pub word: usize,
+}
+
+/// Map from an assembled image back to its source.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugInfo {
+    /// Name of the main source file.
+    pub file: String,
+    /// Source position of each word, in word order.
+    pub lines: Vec<LineEntry>,
+    /// Every label in the program, in word order.
+    pub labels: Vec<Symbol>,
+}
+
+impl DebugInfo {
+    /// Build debug info for a program parsed from `source`.
+    ///
+    /// `file` is only recorded for display.
+    /// Words of a program which wasn't parsed from source get no line entries.
+    #[must_use]
+    pub fn new(program: &Program, source: &str, file: impl Into<String>) -> Self {
+        Self::from_sources(program, &SourceMap::new(file, source))
+    }
+
+    /// Build debug info for a program parsed from several files.
+    ///
+    /// The first file in `sources` is taken as the main file, and entries in any other file
+    /// record its name.
+    #[must_use]
+    pub fn from_sources(program: &Program, sources: &SourceMap) -> Self {
+        let words = word_indices(program);
+        let mut info = Self {
+            file: sources
+                .files()
+                .first()
+                .map(|f| f.name.clone())
+                .unwrap_or_default(),
+            ..Self::default()
+        };
+        for (stat, statement) in program.statements().iter().enumerate() {
+            let word = words[stat];
+            match statement {
+                Statement::Label(name) => info.labels.push(Symbol {
+                    name: name.clone(),
+                    word,
+                }),
+                _ => {
+                    if let Some(span) = program.span_of(stat) {
+                        let (id, span) = sources.local(&span);
+                        let (line, column) = line_col(sources.source(id), span.start);
+                        let file = (id != 0).then(|| sources.name(id).to_owned());
+                        info.lines.push(LineEntry {
+                            word,
+                            line,
+                            column,
+                            file,
+                        });
+                    }
+                }
+            }
+        }
+        info
+    }
+
+    /// Find the source position of a word.
+    #[must_use]
+    pub fn location_of(&self, word: usize) -> Option<&LineEntry> {
+        let i = self.lines.binary_search_by_key(&word, |l| l.word).ok()?;
+        Some(&self.lines[i])
+    }
+
+    /// Find the source position of a statement of `program`, which was disassembled from the image
+    /// this is the debug info for.
+    ///
+    /// Labels aren't in the image, so they have no position.
+    #[must_use]
+    pub fn location_of_statement(&self, program: &Program, stat: StatNum) -> Option<&LineEntry> {
+        if let Statement::Label(_) = program.statements().get(stat)? {
+            return None;
+        }
+        self.location_of(word_indices(program)[stat])
+    }
+
+    /// Names of the labels pointing at a word.
+    pub fn labels_at(&self, word: usize) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(move |l| l.word == word)
+            .map(|l| l.name.as_str())
+    }
+
+    /// Find the word a label points at.
+    #[must_use]
+    pub fn word_of(&self, label: &str) -> Option<usize> {
+        self.labels.iter().find(|l| l.name == label).map(|l| l.word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let source = "10\nloop: ADD [0x10]\n  JUMP :loop\nend:";
+        let program = Program::parse_source(source).expect("parse error");
+        let info = DebugInfo::new(&program, source, "test.ppr");
+
+        assert_eq!(
+            info.location_of(2),
+            Some(&LineEntry {
+                word: 2,
+                line: 3,
+                column: 3,
+                file: None,
+            })
+        );
+        assert_eq!(info.location_of(3), None);
+        assert_eq!(info.labels_at(1).collect::<Vec<_>>(), vec!["loop"]);
+        assert_eq!(info.word_of("end"), Some(3));
+    }
+
+    #[test]
+    fn disassembled_statements() {
+        let source = "10\nloop: ADD [0x10]\n  JUMP :loop\nend:";
+        let program = Program::parse_source(source).expect("parse error");
+        let info = DebugInfo::new(&program, source, "test.ppr");
+        let target = crate::target::TargetSpec::default();
+        let image =
+            crate::assemble::to_bytes(&crate::assemble::assemble(&program, &target).unwrap());
+        let disassembled =
+            crate::disasm::disassemble_with_info(&image, &info).expect("disassembly error");
+
+        let line = |stat| {
+            info.location_of_statement(&disassembled, stat)
+                .map(|l| l.line)
+        };
+        assert_eq!(line(0), Some(1));
+        assert_eq!(line(1), None);
+        assert_eq!(line(3), Some(3));
+        assert_eq!(line(5), None);
+    }
+
+    #[test]
+    fn included_lines() {
+        let mut sources = SourceMap::default();
+        let program = Program::parse_sources(
+            &mut sources,
+            "main.ppr",
+            "10\n.include \"lib.ppr\"",
+            &[],
+            &crate::target::TargetSpec::default(),
+            false,
+            |_| Ok("\n  ADD [0x10]".to_owned()),
+        )
+        .expect("parse error");
+        let info = DebugInfo::from_sources(&program, &sources);
+
+        assert_eq!(info.file, "main.ppr");
+        assert_eq!(info.location_of(0).and_then(|l| l.file.as_deref()), None);
+        assert_eq!(
+            info.location_of(1),
+            Some(&LineEntry {
+                word: 1,
+                line: 2,
+                column: 3,
+                file: Some("lib.ppr".to_owned()),
+            })
+        );
+    }
+}
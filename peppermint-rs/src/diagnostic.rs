@@ -0,0 +1,503 @@
+//! Human-readable rendering of parsing errors.
+//!
+//! Turns an [`Error`] into a report with line and column numbers and snippets of the source, like:
+//! ```text
+//! error: wrong kind of operand
+//!  --> program.ppr:1:6
+//!   |
+//! 1 | LOAD :label
+//!   |      ^^^^^^ expected an address
+//!   | ---- for this instruction
+//! ```
+
+use std::fmt::Write;
+
+use super::{
+    error::{Error, LabelScope, OperandType, Span},
+    source::{FileId, SourceMap},
+};
+
+/// Annotated span of source code in a [`Diagnostic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    /// Span being annotated.
+    pub span: Span,
+    /// Explanation of the span.
+    pub message: String,
+    /// Whether this is the span where the error occurred, rather than extra context.
+    pub primary: bool,
+}
+
+impl Label {
+    fn primary(span: &Span, message: impl Into<String>) -> Self {
+        Self {
+            span: span.clone(),
+            message: message.into(),
+            primary: true,
+        }
+    }
+
+    fn secondary(span: &Span, message: impl Into<String>) -> Self {
+        Self {
+            span: span.clone(),
+            message: message.into(),
+            primary: false,
+        }
+    }
+}
+
+/// Error report ready to be rendered against its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Summary of the error.
+    pub message: String,
+    /// Annotated spans, the primary label comes first.
+    pub labels: Vec<Label>,
+    /// Advice on fixing the error.
+    pub help: Option<String>,
+}
+
+/// Describe an operand type with its article, for use in sentences.
+fn describe_operand(wanted: &OperandType) -> &'static str {
+    match wanted {
+        OperandType::Address => "an address",
+        OperandType::Label => "a label",
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    // one arm per error variant
+    #[allow(clippy::too_many_lines)]
+    fn from(error: &Error) -> Self {
+        let mut help = None;
+        let (message, labels) = match error {
+            Error::InvalidToken { token } => (
+                "invalid token",
+                vec![Label::primary(token, "not recognised")],
+            ),
+            Error::UnexpectedToken { token } => (
+                "unexpected token",
+                vec![Label::primary(
+                    token,
+                    "expected an instruction, literal or label",
+                )],
+            ),
+            Error::UnknownInstruction { token } => (
+                "unknown instruction",
+                vec![Label::primary(token, "not an opcode")],
+            ),
+            Error::MalformedInteger { token } => (
+                "malformed integer",
+                vec![Label::primary(token, "can't be read as an integer")],
+            ),
+            Error::EndOfFile { last_token } => (
+                "unexpected end of file",
+                vec![Label::primary(
+                    last_token,
+                    "this instruction needs an operand",
+                )],
+            ),
+            Error::BadOperand {
+                opcode,
+                operand,
+                wanted,
+            } => (
+                "wrong kind of operand",
+                vec![
+                    Label::primary(operand, format!("expected {}", describe_operand(wanted))),
+                    Label::secondary(opcode, "for this instruction"),
+                ],
+            ),
+            Error::DuplicateLabel { prev, this } => (
+                "label defined twice",
+                vec![
+                    Label::primary(this, "redefined here"),
+                    Label::secondary(prev, "first defined here"),
+                ],
+            ),
+            Error::UndefinedLabel {
+                reference,
+                suggestion,
+                scope,
+            } => {
+                help = suggestion.as_ref().map(|s| format!("did you mean `:{s}`?"));
+                let message = match scope {
+                    LabelScope::Global => "no label with this name".to_owned(),
+                    LabelScope::Local(Some(global)) => {
+                        format!("no local label with this name after `{global}:`")
+                    }
+                    LabelScope::Local(None) => {
+                        "no local label with this name before the first global label".to_owned()
+                    }
+                    LabelScope::Backward => "no label with this number before here".to_owned(),
+                    LabelScope::Forward => "no label with this number after here".to_owned(),
+                };
+                ("undefined label", vec![Label::primary(reference, message)])
+            }
+            Error::UndefinedSymbol {
+                reference,
+                suggestion,
+            } => {
+                help = suggestion.as_ref().map(|s| format!("did you mean `{s}`?"));
+                (
+                    "undefined symbol",
+                    vec![Label::primary(reference, "no symbol with this name")],
+                )
+            }
+            Error::DuplicateSymbol { prev, this } => (
+                "symbol defined twice",
+                vec![
+                    Label::primary(this, "redefined here"),
+                    Label::secondary(prev, "first defined here"),
+                ],
+            ),
+            Error::UnknownDirective { token } => (
+                "unknown directive",
+                vec![Label::primary(token, "not a directive")],
+            ),
+            Error::MissingArgument { directive, usage } => (
+                "missing directive argument",
+                vec![Label::primary(directive, format!("expected `{usage}`"))],
+            ),
+            Error::IncludeNotFound { directive, path } => (
+                "included file not found",
+                vec![Label::primary(
+                    directive,
+                    format!("no `{path}` next to this file or in the include paths"),
+                )],
+            ),
+            Error::IncludeCycle { directive } => (
+                "include cycle",
+                vec![Label::primary(
+                    directive,
+                    "this file is already being included",
+                )],
+            ),
+            Error::DuplicateMacro { prev, this } => (
+                "macro defined twice",
+                vec![
+                    Label::primary(this, "redefined here"),
+                    Label::secondary(prev, "first defined here"),
+                ],
+            ),
+            Error::UnterminatedMacro { definition } => (
+                "unterminated macro",
+                vec![Label::primary(definition, "no `.endm` for this macro")],
+            ),
+            Error::WrongArgumentCount {
+                invocation,
+                expected,
+                found,
+            } => (
+                "wrong number of macro arguments",
+                vec![Label::primary(
+                    invocation,
+                    format!("expected {expected} arguments, found {found}"),
+                )],
+            ),
+            Error::MacroRecursion { invocation } => {
+                help = Some("a macro can't invoke itself, even indirectly".to_owned());
+                (
+                    "recursive macro invocation",
+                    vec![Label::primary(invocation, "already being expanded")],
+                )
+            }
+            Error::InMacro { invocation, error } => {
+                let mut diagnostic = Self::from(error.as_ref());
+                diagnostic
+                    .labels
+                    .push(Label::secondary(invocation, "in this macro invocation"));
+                return diagnostic;
+            }
+            Error::OutOfMemory { directive, cells } => (
+                "out of memory",
+                vec![Label::primary(
+                    directive,
+                    format!("no run of {cells} free cells left"),
+                )],
+            ),
+            Error::ExternOutsideObject { directive } => {
+                help = Some("assemble this file as an object and link it".to_owned());
+                (
+                    "imported label outside of an object",
+                    vec![Label::primary(directive, "only objects can import labels")],
+                )
+            }
+            Error::ReservationInObject { directive } => {
+                help = Some("give a fixed address a name with `.equ` instead".to_owned());
+                (
+                    "memory reserved in an object",
+                    vec![Label::primary(directive, "objects can't reserve memory")],
+                )
+            }
+            Error::AddressOutOfRange { address, max } => (
+                "address out of range",
+                vec![Label::primary(
+                    address,
+                    format!("the largest address is 0x{max:x}"),
+                )],
+            ),
+            Error::LiteralOutOfRange { literal, max } => (
+                "literal out of range",
+                vec![Label::primary(
+                    literal,
+                    format!("the largest literal is {max}"),
+                )],
+            ),
+            Error::UnsupportedInstruction { instruction } => {
+                help = Some("set `instruction-set = \"extended\"` in the target file".to_owned());
+                (
+                    "unsupported instruction",
+                    vec![Label::primary(
+                        instruction,
+                        "this instruction isn't in the base instruction set",
+                    )],
+                )
+            }
+        };
+
+        Self {
+            message: message.to_owned(),
+            labels,
+            help,
+        }
+    }
+}
+
+/// Find the 1-based line and column of a byte offset in `source`.
+///
+/// Columns count characters, not bytes.
+#[must_use]
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let col = source[line_start..offset].chars().count() + 1;
+    (line, col)
+}
+
+/// Get the full text of the line containing a byte offset, along with its starting offset.
+fn line_at(source: &str, offset: usize) -> (usize, &str) {
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    (start, &source[start..end])
+}
+
+impl Diagnostic {
+    /// Render the diagnostic against the source it came from.
+    ///
+    /// `file_name` is only used for display.
+    #[must_use]
+    pub fn render(&self, source: &str, file_name: &str) -> String {
+        self.render_files(&[(file_name, source, self.labels.clone())])
+    }
+
+    /// Render the diagnostic against a program made of several files.
+    ///
+    /// Labels are grouped by file, starting with the file of the primary label.
+    #[must_use]
+    pub fn render_sources(&self, sources: &SourceMap) -> String {
+        let mut files: Vec<(FileId, Vec<Label>)> = Vec::new();
+        for label in &self.labels {
+            let (file, span) = sources.local(&label.span);
+            let label = Label {
+                span,
+                ..label.clone()
+            };
+            match files.iter_mut().find(|(f, _)| *f == file) {
+                Some((_, labels)) => labels.push(label),
+                None => files.push((file, vec![label])),
+            }
+        }
+        let files: Vec<_> = files
+            .into_iter()
+            .map(|(file, labels)| (sources.name(file), sources.source(file), labels))
+            .collect();
+        self.render_files(&files)
+    }
+
+    /// Render labels grouped by file, each group given as a file name, its source and its labels.
+    fn render_files(&self, files: &[(&str, &str, Vec<Label>)]) -> String {
+        let mut out = format!("error: {}\n", self.message);
+        let gutter = files
+            .iter()
+            .flat_map(|(_, source, labels)| {
+                labels
+                    .iter()
+                    .map(|label| line_col(source, label.span.start).0)
+            })
+            .map(|line| line.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        for (i, (file_name, source, labels)) in files.iter().enumerate() {
+            let Some(first) = labels.first() else {
+                continue;
+            };
+            let (line, col) = line_col(source, first.span.start);
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            let _ = writeln!(out, "{:gutter$}{arrow} {file_name}:{line}:{col}", "");
+            let _ = writeln!(out, "{:gutter$} |", "");
+
+            let mut labels: Vec<_> = labels
+                .iter()
+                .map(|label| (line_col(source, label.span.start).0, label))
+                .collect();
+            // keep the primary label first on its line
+            labels.sort_by_key(|(line, label)| (*line, !label.primary));
+            let mut prev_line = None;
+            for (line, label) in labels {
+                let (line_start, text) = line_at(source, label.span.start);
+                if prev_line != Some(line) {
+                    if prev_line.is_some_and(|prev| line > prev + 1) {
+                        let _ = writeln!(out, "{:gutter$}...", "");
+                    }
+                    let _ = writeln!(out, "{line:>gutter$} | {text}");
+                    prev_line = Some(line);
+                }
+
+                // mirror tabs so the underline stays aligned
+                let padding: String = text[..label.span.start - line_start]
+                    .chars()
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let end = label.span.end.min(line_start + text.len());
+                let width = source[label.span.start..end].chars().count().max(1);
+                let marker = if label.primary { "^" } else { "-" };
+                let _ = writeln!(
+                    out,
+                    "{:gutter$} | {padding}{} {}",
+                    "",
+                    marker.repeat(width),
+                    label.message,
+                );
+            }
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{:gutter$} = help: {help}", "");
+        }
+
+        out
+    }
+}
+
+impl Error<Span> {
+    /// Render this error against the source it came from.
+    ///
+    /// See [`Diagnostic::render`].
+    #[must_use]
+    pub fn render(&self, source: &str, file_name: &str) -> String {
+        Diagnostic::from(self).render(source, file_name)
+    }
+
+    /// Render this error against a program made of several files.
+    ///
+    /// See [`Diagnostic::render_sources`].
+    #[must_use]
+    pub fn render_sources(&self, sources: &SourceMap) -> String {
+        Diagnostic::from(self).render_sources(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("LOAD", 0 => (1, 1))]
+    #[test_case("10\nLOAD [0x10]", 8 => (2, 6))]
+    #[test_case("10\n\n20", 4 => (3, 1))]
+    fn position(source: &str, offset: usize) -> (usize, usize) {
+        line_col(source, offset)
+    }
+
+    #[test]
+    fn render_bad_operand() {
+        let source = "10\nLOAD :label\n";
+        let error = Error::BadOperand {
+            opcode: 3..7,
+            operand: 8..14,
+            wanted: OperandType::Address,
+        };
+
+        assert_eq!(
+            error.render(source, "test.ppr"),
+            "error: wrong kind of operand
+ --> test.ppr:2:6
+  |
+2 | LOAD :label
+  |      ^^^^^^ expected an address
+  | ---- for this instruction
+"
+        );
+    }
+
+    #[test]
+    fn render_help() {
+        let source = "loop: JUMP :lop";
+        let error = Error::UndefinedLabel {
+            reference: 11..15,
+            suggestion: Some("loop".to_owned()),
+            scope: LabelScope::Global,
+        };
+
+        assert_eq!(
+            error.render(source, "test.ppr"),
+            "error: undefined label
+ --> test.ppr:1:12
+  |
+1 | loop: JUMP :lop
+  |            ^^^^ no label with this name
+  = help: did you mean `:loop`?
+"
+        );
+    }
+
+    #[test]
+    fn render_across_lines() {
+        let source = "a: 10\n20\na: 30";
+        let error = Error::DuplicateLabel {
+            prev: 0..2,
+            this: 9..11,
+        };
+
+        assert_eq!(
+            error.render(source, "test.ppr"),
+            "error: label defined twice
+ --> test.ppr:3:1
+  |
+1 | a: 10
+  | -- first defined here
+ ...
+3 | a: 30
+  | ^^ redefined here
+"
+        );
+    }
+
+    #[test]
+    fn render_several_files() {
+        let mut sources = SourceMap::new("main.ppr", "a: 10");
+        sources.add("lib.ppr".to_owned(), "20\na: 30");
+        let error = Error::DuplicateLabel {
+            prev: 0..2,
+            this: 9..11,
+        };
+
+        assert_eq!(
+            error.render_sources(&sources),
+            "error: label defined twice
+ --> lib.ppr:2:1
+  |
+2 | a: 30
+  | ^^ redefined here
+ ::: main.ppr:1:1
+  |
+1 | a: 10
+  | -- first defined here
+"
+        );
+    }
+}
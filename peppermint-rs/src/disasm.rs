@@ -0,0 +1,233 @@
+//! Reconstruction of programs from Tick Talk machine code.
+//!
+//! This is the inverse of [`crate::assemble`]; see there for the encoding.
+//! Label names don't survive assembly, so a label called `Lnnnn` (after the word index) is
+//! synthesised for every jump target, unless [`DebugInfo`] is given to name them.
+//! Synthesised names get a `_` appended while they clash with a name from the debug info.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use super::{
+    assemble::{INSTRUCTION_FLAG, OPCODE_SHIFT, OPERAND_MASK},
+    debug_info::DebugInfo,
+    labels::synthesise_name,
+    Address, DoubleWord, Instruction, Program, StatNum, Statement,
+};
+
+/// Error in malformed machine code.
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum Error {
+    /// Image doesn't contain a whole number of words.
+    #[error("image is {len} bytes long, which isn't a whole number of words")]
+    TruncatedWord {
+        /// Length of the image in bytes.
+        len: usize,
+    },
+    /// Instruction word with an opcode outside the instruction set.
+    #[error("unknown opcode {opcode} in word {word}")]
+    UnknownOpcode {
+        /// Index of the offending word.
+        word: usize,
+        /// The unrecognised opcode.
+        opcode: DoubleWord,
+    },
+    /// Jump to a word past the end of the image.
+    #[error("jump in word {word} targets word {target}, which is past the end of the image")]
+    JumpOutOfRange {
+        /// Index of the offending word.
+        word: usize,
+        /// The target word index.
+        target: usize,
+    },
+}
+
+/// Decode a single word, leaving jump targets as word indices.
+fn decode(word_index: usize, word: DoubleWord) -> Result<Statement<usize>, Error> {
+    if word & INSTRUCTION_FLAG == 0 {
+        return Ok(Statement::Literal(word));
+    }
+
+    let opcode = (word & !INSTRUCTION_FLAG) >> OPCODE_SHIFT;
+    let operand: Address = word & OPERAND_MASK;
+    let ins = match opcode {
+        0 => Instruction::Load(operand),
+        1 => Instruction::And(operand),
+        2 => Instruction::Xor(operand),
+        3 => Instruction::Or(operand),
+        4 => Instruction::Add(operand),
+        5 => Instruction::Sub(operand),
+        6 => Instruction::Store(operand),
+        7 => Instruction::Jump(operand as usize),
+        8 => Instruction::JumpZ(operand as usize),
+        9 => Instruction::JumpN(operand as usize),
+        10 => Instruction::Halt,
+        _ => {
+            return Err(Error::UnknownOpcode {
+                word: word_index,
+                opcode,
+            })
+        }
+    };
+
+    Ok(Statement::InstrLine(ins))
+}
+
+/// Disassemble a big-endian byte image into a program.
+///
+/// # Errors
+///
+/// Throws [`enum@Error`] if the image isn't valid machine code.
+pub fn disassemble(image: &[u8]) -> Result<Program, Error> {
+    disassemble_labelled(image, |_| Vec::new())
+}
+
+/// Disassemble a big-endian byte image into a program, taking label names from debug info.
+///
+/// Every label in `info` is restored, including those which no jump refers to.
+/// Jump targets without a label in `info` get a synthesised one.
+///
+/// # Errors
+///
+/// Throws [`enum@Error`] if the image isn't valid machine code.
+pub fn disassemble_with_info(image: &[u8], info: &DebugInfo) -> Result<Program, Error> {
+    disassemble_labelled(image, |word| {
+        info.labels_at(word).map(str::to_owned).collect()
+    })
+}
+
+/// Disassemble an image, with `labels` giving the names of the labels at each word.
+fn disassemble_labelled(
+    image: &[u8],
+    labels: impl Fn(usize) -> Vec<String>,
+) -> Result<Program, Error> {
+    if !image.len().is_multiple_of(2) {
+        return Err(Error::TruncatedWord { len: image.len() });
+    }
+
+    let decoded = image
+        .chunks_exact(2)
+        .map(|pair| DoubleWord::from_be_bytes([pair[0], pair[1]]))
+        .enumerate()
+        .map(|(i, word)| decode(i, word))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut targets = BTreeSet::new();
+    for (i, stat) in decoded.iter().enumerate() {
+        if let Statement::InstrLine(
+            Instruction::Jump(target) | Instruction::JumpZ(target) | Instruction::JumpN(target),
+        ) = stat
+        {
+            // jumping to just past the end is how a program halts
+            if *target > decoded.len() {
+                return Err(Error::JumpOutOfRange {
+                    word: i,
+                    target: *target,
+                });
+            }
+            targets.insert(*target);
+        }
+    }
+
+    // lay out the statements with labels in front of their targets
+    let given: Vec<Vec<String>> = (0..=decoded.len()).map(labels).collect();
+    let mut taken: HashSet<String> = given.iter().flatten().cloned().collect();
+    let mut statements = Vec::with_capacity(decoded.len() + targets.len());
+    let mut label_stats: HashMap<usize, StatNum> = HashMap::with_capacity(targets.len());
+    for (i, mut names) in given.into_iter().enumerate() {
+        if names.is_empty() && targets.contains(&i) {
+            names.push(synthesise_name(i, &mut taken));
+        }
+        if !names.is_empty() {
+            label_stats.insert(i, statements.len());
+        }
+        statements.extend(names.into_iter().map(Statement::Label));
+        if let Some(stat) = decoded.get(i) {
+            statements.push(stat.clone());
+        }
+    }
+
+    let statements = statements
+        .into_iter()
+        .map(|stat| stat.map_label(|target| label_stats[&target]))
+        .collect();
+
+    Ok(Program {
+        statements,
+        spans: Vec::new(),
+        operands: Vec::new(),
+        globals: Vec::new(),
+        externs: Vec::new(),
+        memory: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(&[0x00, 0x0A, 0xB0, 0x20] => "    10\n    STORE [0x20]\n"; "no labels")]
+    #[test_case(&[0x00, 0x01, 0xB8, 0x00] => "L0000:\n    1\n    JUMP :L0000\n"; "backwards jump")]
+    #[test_case(&[0xB8, 0x02, 0x00, 0x01] => "    JUMP :L0002\n    1\nL0002:\n"; "jump to end")]
+    #[test_case(&[0xC0, 0x02, 0xC8, 0x00, 0xD0, 0x00] => "L0000:\n    JUMPZ :L0002\n    JUMPN :L0000\nL0002:\n    HALT\n"; "extended")]
+    fn disassemble_image(image: &[u8]) -> String {
+        disassemble(image).expect("disassembly error").to_string()
+    }
+
+    #[test_case(&[0x00] => Error::TruncatedWord { len: 1 })]
+    #[test_case(&[0xD8, 0x00] => Error::UnknownOpcode { word: 0, opcode: 11 })]
+    #[test_case(&[0xB8, 0x05] => Error::JumpOutOfRange { word: 0, target: 5 })]
+    fn disassemble_error(image: &[u8]) -> Error {
+        disassemble(image).expect_err("no error thrown")
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = "10 STORE [0x20] loop: ADD [0x20] STORE [0x20] JUMP :loop";
+        let program = Program::parse_source(source).expect("parse error");
+        let target = crate::target::TargetSpec::default();
+        let image =
+            crate::assemble::to_bytes(&crate::assemble::assemble(&program, &target).unwrap());
+        let disassembled = disassemble(&image).expect("disassembly error");
+
+        let reassembled = crate::assemble::assemble(&disassembled, &target).unwrap();
+        assert_eq!(crate::assemble::to_bytes(&reassembled), image);
+    }
+
+    #[test]
+    fn with_info() {
+        let source = "start: 10 STORE [0x20] loop: ADD [0x20] JUMP :loop";
+        let program = Program::parse_source(source).expect("parse error");
+        let target = crate::target::TargetSpec::default();
+        let image =
+            crate::assemble::to_bytes(&crate::assemble::assemble(&program, &target).unwrap());
+        let info = DebugInfo::new(&program, source, "test.ppr");
+
+        assert_eq!(
+            disassemble_with_info(&image, &info)
+                .expect("disassembly error")
+                .to_string(),
+            "start:\n    10\n    STORE [0x20]\nloop:\n    ADD [0x20]\n    JUMP :loop\n"
+        );
+    }
+
+    #[test]
+    fn synthesised_label_clash() {
+        // debug info for another program, which has a label named like a synthesised one
+        let source = "L0002: 1 2";
+        let program = Program::parse_source(source).expect("parse error");
+        let info = DebugInfo::new(&program, source, "test.ppr");
+        let image = [0x00, 0x01, 0xB8, 0x02];
+
+        let disassembled = disassemble_with_info(&image, &info).expect("disassembly error");
+        let output = disassembled.to_string();
+        assert_eq!(output, "L0002:\n    1\n    JUMP :L0002_\nL0002_:\n");
+
+        let target = crate::target::TargetSpec::default();
+        let reassembled = Program::parse_source(&output).expect("parse error");
+        assert_eq!(
+            crate::assemble::to_bytes(&crate::assemble::assemble(&reassembled, &target).unwrap()),
+            image
+        );
+    }
+}
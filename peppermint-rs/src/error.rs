@@ -2,6 +2,8 @@
 
 use std::{fmt::Debug, ops::Range};
 
+use super::{Address, Literal};
+
 /// Span of an error context in source code.
 pub type Span = Range<usize>;
 
@@ -14,6 +16,21 @@ pub enum OperandType {
     Label,
 }
 
+/// Where a jump looked for its label.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LabelScope {
+    /// Among global labels, like `:main`.
+    Global,
+    /// Among local labels after a global label, like `:.loop`.
+    ///
+    /// [`None`] for local labels before the first global label.
+    Local(Option<String>),
+    /// At the nearest numeric label before the jump, like `:1b`.
+    Backward,
+    /// At the nearest numeric label after the jump, like `:1f`.
+    Forward,
+}
+
 /// Error in malformed input.
 #[derive(Debug, PartialEq, Clone, thiserror::Error)]
 #[allow(clippy::module_name_repetitions)]
@@ -68,58 +85,336 @@ pub enum Error<S: Debug = Span> {
         /// Span of the next time (this time) this label appeared.
         this: S,
     },
+    /// Jump to a label which is never defined.
+    #[error("jump to undefined label at {reference:#?}")]
+    UndefinedLabel {
+        /// Span of the label reference in the jump instruction.
+        reference: S,
+        /// Most similar label which is defined in reach of the jump, if there is one close enough.
+        suggestion: Option<String>,
+        /// Labels the jump could have reached.
+        scope: LabelScope,
+    },
+    /// Reference to a symbol which is never defined.
+    #[error("reference to undefined symbol at {reference:#?}")]
+    UndefinedSymbol {
+        /// Span of the symbol reference.
+        reference: S,
+        /// Most similar symbol which is defined, if there is one close enough.
+        suggestion: Option<String>,
+    },
+    /// Non-unique symbol name in source code.
+    #[error(
+        "symbol defined twice in file\nfirst occurrence: {prev:#?}\nsecond occurence: {this:#?}"
+    )]
+    DuplicateSymbol {
+        /// Span of the directive first defining this symbol.
+        prev: S,
+        /// Span of the directive defining it again.
+        this: S,
+    },
+    /// Directive which the assembler doesn't know.
+    #[error("unknown directive at {token:#?}")]
+    UnknownDirective {
+        /// Span of the directive.
+        token: S,
+    },
+    /// Directive without all of its arguments.
+    #[error("directive at {directive:#?} is missing arguments, expected `{usage}`")]
+    MissingArgument {
+        /// Span of the directive and the arguments it does have.
+        directive: S,
+        /// How the directive is written, like `.equ name value`.
+        usage: &'static str,
+    },
+    /// Included file which isn't in any of the include paths.
+    #[error("couldn't find included file `{path}` at {directive:#?}")]
+    IncludeNotFound {
+        /// Span of the `.include` directive.
+        directive: S,
+        /// Path as written in the directive.
+        path: String,
+    },
+    /// File which includes itself, directly or through other files.
+    #[error("include at {directive:#?} would include a file inside itself")]
+    IncludeCycle {
+        /// Span of the `.include` directive.
+        directive: S,
+    },
+    /// Macro name which is already taken.
+    #[error(
+        "macro defined twice in file\nfirst occurrence: {prev:#?}\nsecond occurence: {this:#?}"
+    )]
+    DuplicateMacro {
+        /// Span of the first definition's header.
+        prev: S,
+        /// Span of this definition's header.
+        this: S,
+    },
+    /// `.macro` without a matching `.endm`.
+    #[error("macro defined at {definition:#?} is never ended with `.endm`")]
+    UnterminatedMacro {
+        /// Span of the definition's header.
+        definition: S,
+    },
+    /// Macro invoked with the wrong number of arguments.
+    #[error("macro invoked at {invocation:#?} with {found} arguments, but it takes {expected}")]
+    WrongArgumentCount {
+        /// Span of the invocation.
+        invocation: S,
+        /// Number of parameters the macro has.
+        expected: usize,
+        /// Number of arguments given.
+        found: usize,
+    },
+    /// Invocation of a macro inside its own expansion, directly or through other macros.
+    #[error("macro invoked at {invocation:#?} is already being expanded")]
+    MacroRecursion {
+        /// Span of the invocation which would be expanded.
+        invocation: S,
+    },
+    /// Error in the statements produced by expanding a macro.
+    ///
+    /// The spans of `error` point into the body of the macro.
+    #[error("in the macro invoked at {invocation:#?}: {error}")]
+    InMacro {
+        /// Span of the invocation.
+        invocation: S,
+        /// The error in the expansion.
+        error: Box<Error<S>>,
+    },
+    /// Reservation of memory which doesn't fit in the free cells.
+    #[error("no room for the {cells} cells reserved at {directive:#?}")]
+    OutOfMemory {
+        /// Span of the reserving directive.
+        directive: S,
+        /// Number of cells reserved.
+        cells: Address,
+    },
+    /// `.extern` in a file which isn't being assembled as an object.
+    #[error("label imported at {directive:#?} can only be resolved by linking")]
+    ExternOutsideObject {
+        /// Span of the `.extern` directive.
+        directive: S,
+    },
+    /// `.word` or `.array` in a file being assembled as an object.
+    #[error("memory reserved at {directive:#?} in an object")]
+    ReservationInObject {
+        /// Span of the reserving directive.
+        directive: S,
+    },
+    /// Address which doesn't fit in the target's address space.
+    #[error("address at {address:#?} is out of range, the largest address is 0x{max:x}")]
+    AddressOutOfRange {
+        /// Span of the address operand.
+        address: S,
+        /// Largest address on the target.
+        max: Address,
+    },
+    /// Literal which doesn't fit in the target's literal width.
+    #[error("literal at {literal:#?} is out of range, the largest literal is {max}")]
+    LiteralOutOfRange {
+        /// Span of the literal.
+        literal: S,
+        /// Largest literal on the target.
+        max: Literal,
+    },
+    /// Instruction which isn't in the target's instruction set.
+    #[error("instruction at {instruction:#?} isn't supported by the target")]
+    UnsupportedInstruction {
+        /// Span of the instruction.
+        instruction: S,
+    },
 }
 
-impl Error<Span> {
-    /// Given a `source`, convert the numeric spans in the error to string slices.
-    ///
-    /// Use this to increase readability of error messages when they are to be returned to the user:
-    /// ```rust
-    /// use peppermint::Program;
-    ///
-    /// let source = "LOAD [0x10]";
-    /// let my_prog = Program::parse_source(source)
-    ///     .map_err(|e| e.spans_to_source(source))
-    ///     .expect("parse error");
-    /// ```
-    pub fn spans_to_source(self, source: &str) -> Error<&str> {
+impl<S: Debug> Error<S> {
+    /// Every span referred to by this error, in the order they are declared.
+    pub fn spans(&self) -> Vec<&S> {
         match self {
-            Self::InvalidToken { token: span } => Error::InvalidToken {
-                token: get_span(span, source),
-            },
-            Self::UnexpectedToken { token: span } => Error::InvalidToken {
-                token: get_span(span, source),
-            },
-            Self::UnknownInstruction { token: span } => Error::UnknownInstruction {
-                token: get_span(span, source),
-            },
-            Self::MalformedInteger { token: span } => Error::MalformedInteger {
-                token: get_span(span, source),
-            },
+            Self::InvalidToken { token }
+            | Self::UnexpectedToken { token }
+            | Self::UnknownInstruction { token }
+            | Self::MalformedInteger { token }
+            | Self::UnknownDirective { token } => vec![token],
+            Self::IncludeNotFound { directive, .. } | Self::IncludeCycle { directive } => {
+                vec![directive]
+            }
+            Self::UnterminatedMacro { definition } => vec![definition],
+            Self::WrongArgumentCount { invocation, .. } | Self::MacroRecursion { invocation } => {
+                vec![invocation]
+            }
+            Self::InMacro { invocation, error } => {
+                let mut spans = vec![invocation];
+                spans.extend(error.spans());
+                spans
+            }
+            Self::EndOfFile { last_token } => vec![last_token],
+            Self::BadOperand {
+                opcode, operand, ..
+            } => vec![opcode, operand],
+            Self::DuplicateLabel { prev, this }
+            | Self::DuplicateSymbol { prev, this }
+            | Self::DuplicateMacro { prev, this } => {
+                vec![prev, this]
+            }
+            Self::UndefinedLabel { reference, .. } | Self::UndefinedSymbol { reference, .. } => {
+                vec![reference]
+            }
+            Self::MissingArgument { directive, .. }
+            | Self::OutOfMemory { directive, .. }
+            | Self::ExternOutsideObject { directive }
+            | Self::ReservationInObject { directive } => vec![directive],
+            Self::AddressOutOfRange { address, .. } => vec![address],
+            Self::LiteralOutOfRange { literal, .. } => vec![literal],
+            Self::UnsupportedInstruction { instruction } => vec![instruction],
+        }
+    }
+}
+
+impl<S: Debug> Error<S> {
+    /// Convert every span in the error with `f`.
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn map_spans<T: Debug>(self, f: &mut impl FnMut(S) -> T) -> Error<T> {
+        match self {
+            Self::InvalidToken { token: span } => Error::InvalidToken { token: f(span) },
+            Self::UnexpectedToken { token: span } => Error::UnexpectedToken { token: f(span) },
+            Self::UnknownInstruction { token: span } => {
+                Error::UnknownInstruction { token: f(span) }
+            }
+            Self::MalformedInteger { token: span } => Error::MalformedInteger { token: f(span) },
             Self::EndOfFile { last_token } => Error::EndOfFile {
-                last_token: get_span(last_token, source),
+                last_token: f(last_token),
             },
             Self::BadOperand {
                 opcode: opcode_span,
                 operand: operand_span,
                 wanted: wanted_operand,
             } => Error::BadOperand {
-                opcode: get_span(opcode_span, source),
-                operand: get_span(operand_span, source),
+                opcode: f(opcode_span),
+                operand: f(operand_span),
                 wanted: wanted_operand,
             },
             Self::DuplicateLabel {
                 prev: prev_span,
                 this: this_span,
             } => Error::DuplicateLabel {
-                prev: get_span(prev_span, source),
-                this: get_span(this_span, source),
+                prev: f(prev_span),
+                this: f(this_span),
+            },
+            Self::UndefinedLabel {
+                reference,
+                suggestion,
+                scope,
+            } => Error::UndefinedLabel {
+                reference: f(reference),
+                suggestion,
+                scope,
+            },
+            Self::UndefinedSymbol {
+                reference,
+                suggestion,
+            } => Error::UndefinedSymbol {
+                reference: f(reference),
+                suggestion,
+            },
+            Self::DuplicateSymbol { prev, this } => Error::DuplicateSymbol {
+                prev: f(prev),
+                this: f(this),
+            },
+            Self::UnknownDirective { token } => Error::UnknownDirective { token: f(token) },
+            Self::MissingArgument { directive, usage } => Error::MissingArgument {
+                directive: f(directive),
+                usage,
+            },
+            Self::IncludeNotFound { directive, path } => Error::IncludeNotFound {
+                directive: f(directive),
+                path,
+            },
+            Self::IncludeCycle { directive } => Error::IncludeCycle {
+                directive: f(directive),
+            },
+            Self::DuplicateMacro { prev, this } => Error::DuplicateMacro {
+                prev: f(prev),
+                this: f(this),
+            },
+            Self::UnterminatedMacro { definition } => Error::UnterminatedMacro {
+                definition: f(definition),
+            },
+            Self::WrongArgumentCount {
+                invocation,
+                expected,
+                found,
+            } => Error::WrongArgumentCount {
+                invocation: f(invocation),
+                expected,
+                found,
+            },
+            Self::MacroRecursion { invocation } => Error::MacroRecursion {
+                invocation: f(invocation),
+            },
+            Self::InMacro { invocation, error } => Error::InMacro {
+                invocation: f(invocation),
+                error: Box::new(error.map_spans(f)),
+            },
+            Self::OutOfMemory { directive, cells } => Error::OutOfMemory {
+                directive: f(directive),
+                cells,
+            },
+            Self::ExternOutsideObject { directive } => Error::ExternOutsideObject {
+                directive: f(directive),
+            },
+            Self::ReservationInObject { directive } => Error::ReservationInObject {
+                directive: f(directive),
             },
+            Self::AddressOutOfRange { address, max } => Error::AddressOutOfRange {
+                address: f(address),
+                max,
+            },
+            Self::LiteralOutOfRange { literal, max } => Error::LiteralOutOfRange {
+                literal: f(literal),
+                max,
+            },
+            Self::UnsupportedInstruction { instruction } => Error::UnsupportedInstruction {
+                instruction: f(instruction),
+            },
+        }
+    }
+}
+
+impl Error<Span> {
+    /// Smallest span which covers every span in this error.
+    ///
+    /// Errors in a macro expansion are covered by the span of the invocation alone, since the body
+    /// of the macro can be anywhere in the source.
+    #[must_use]
+    pub fn covering_span(&self) -> Span {
+        if let Self::InMacro { invocation, .. } = self {
+            return invocation.clone();
         }
+        let spans = self.spans();
+        let start = spans.iter().map(|s| s.start).min().unwrap_or_default();
+        let end = spans.iter().map(|s| s.end).max().unwrap_or_default();
+        start..end
+    }
+
+    /// Given a `source`, convert the numeric spans in the error to string slices.
+    ///
+    /// Use this to increase readability of error messages when they are to be returned to the user:
+    /// ```rust
+    /// use peppermint::Program;
+    ///
+    /// let source = "LOAD [0x10]";
+    /// let my_prog = Program::parse_source(source)
+    ///     .map_err(|e| e.spans_to_source(source))
+    ///     .expect("parse error");
+    /// ```
+    #[must_use]
+    pub fn spans_to_source(self, source: &str) -> Error<&str> {
+        self.map_spans(&mut |span| get_span(span, source))
     }
 }
 
 /// Get the section of the source that the error refers to.
-fn get_span<'a>(span: Span, source: &'a str) -> &'a str {
+fn get_span(span: Span, source: &str) -> &str {
     &source[span]
 }
@@ -0,0 +1,172 @@
+//! Canonical formatting of Peppermint source.
+//!
+//! The canonical layout puts one statement on each line, with opcodes in uppercase and addresses
+//! in hex.
+//! Labels are placed in a column to the left of the statement they point to, and the code column
+//! is aligned to fit the longest label.
+//! Comments and single blank lines are preserved; comments which trail code stay on the same line.
+
+use super::{
+    error::{Error, Span},
+    include, lex, macros,
+    target::TargetSpec,
+    Parsed, Statement,
+};
+
+/// One item of source code in order of appearance.
+enum Item {
+    Label(String),
+    /// Rendered statement which can share a line with a label.
+    Code(String),
+    /// Rendered directive or macro invocation, which always gets a line of its own.
+    Directive(String),
+    Comment,
+}
+
+/// One line of formatted output.
+#[derive(Default)]
+struct Line {
+    label: Option<String>,
+    code: Option<String>,
+    comment: Option<String>,
+}
+
+impl Line {
+    fn is_empty(&self) -> bool {
+        self.label.is_none() && self.code.is_none() && self.comment.is_none()
+    }
+}
+
+/// Format source code into the canonical layout.
+///
+/// # Errors
+///
+/// Throws [`Error`] if the source is syntactically invalid.
+pub fn format_source(input: &str) -> Result<String, Error> {
+    let lexed = lex::tokenise_recovering(input);
+    if let Some(e) = lexed.errors.into_iter().next() {
+        return Err(e);
+    }
+    let (tokens, includes) = include::split_includes(lexed.tokens)?;
+    let (tokens, macro_syntax) = macros::split_syntax(tokens, input)?;
+    let mut stream = tokens.into_iter().peekable();
+    let same_line = |before: &Span, after: &Span| macros::same_line(input, before, after);
+    // formatting doesn't depend on the target, which only limits values no target can hold here
+    let target = TargetSpec::default();
+    let mut items: Vec<(Item, Span)> =
+        std::iter::from_fn(|| Parsed::take_from_token_stream(&mut stream, &same_line, &target))
+            .map(|res| {
+                res.map(|parsed| match parsed {
+                    Parsed::Statement(stat) => match stat.statement {
+                        Statement::Label(name) => (Item::Label(name), stat.span),
+                        _ => (Item::Code(stat.to_string()), stat.span),
+                    },
+                    Parsed::Directive(directive, span) => {
+                        (Item::Directive(directive.to_string()), span)
+                    }
+                })
+            })
+            .collect::<Result<_, _>>()?;
+    items.extend(
+        includes
+            .into_iter()
+            .chain(macro_syntax)
+            .map(|(text, span)| (Item::Directive(text), span)),
+    );
+    items.extend(lexed.comments.into_iter().map(|span| (Item::Comment, span)));
+    items.sort_by_key(|(_, span)| span.start);
+
+    let mut lines: Vec<Line> = Vec::new();
+    let mut current = Line::default();
+    let mut prev_end = None;
+    for (item, span) in items {
+        let gap = prev_end.map_or("", |end| &input[end..span.start]);
+        let newlines = gap.matches('\n').count();
+        prev_end = Some(span.end);
+
+        if matches!(item, Item::Comment) && newlines == 0 && !current.is_empty() {
+            current.comment = Some(input[span].trim_end().to_owned());
+            continue;
+        }
+
+        // a label on its own waits for the next statement to share its line
+        let attach_to_label = current.label.is_some()
+            && current.code.is_none()
+            && current.comment.is_none()
+            && matches!(item, Item::Code(_));
+        if !attach_to_label {
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            if newlines > 1 && !lines.is_empty() {
+                lines.push(Line::default());
+            }
+        }
+
+        match item {
+            Item::Comment => current.comment = Some(input[span].trim_end().to_owned()),
+            Item::Label(name) => current.label = Some(name),
+            Item::Code(code) | Item::Directive(code) => current.code = Some(code),
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    // code goes after the widest label, but always at least one indent in
+    let indent = lines
+        .iter()
+        .filter_map(|line| line.label.as_ref())
+        .map(|label| label.len() + 2)
+        .max()
+        .unwrap_or(0)
+        .max(4);
+
+    let mut output = String::new();
+    for line in lines {
+        let mut text = String::new();
+        if let Some(label) = &line.label {
+            text.push_str(label);
+            text.push(':');
+        }
+        if let Some(code) = &line.code {
+            text = format!("{text:indent$}{code}");
+        }
+        if let Some(comment) = &line.comment {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(comment);
+        }
+        output.push_str(&text);
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("load [16]" => "    LOAD [0x10]\n"; "canonical operands")]
+    #[test_case("10 store [0x20]" => "    10\n    STORE [0x20]\n"; "one statement per line")]
+    #[test_case("loop:\nadd [0x11] jump :loop" => "loop: ADD [0x11]\n      JUMP :loop\n"; "labels attach")]
+    #[test_case("a: b:\n10" => "a:\nb:  10\n"; "stacked labels")]
+    #[test_case(".equ  counter 32\nadd [counter+1] limit" => "    .equ counter 0x20\n    ADD [counter+1]\n    limit\n"; "symbols")]
+    #[test_case(".include   \"lib.ppr\" 10" => "    .include \"lib.ppr\"\n    10\n"; "includes")]
+    #[test_case(".macro  inc cell\nload [cell] add [one] store [cell]\n.endm\ninc   [0x10]" => "    .macro inc cell\n    LOAD [cell]\n    ADD [one]\n    STORE [cell]\n    .endm\n    inc [0x10]\n"; "macros")]
+    #[test_case("; header\n\n\n10 ; trailing\n# own line\n20" => "; header\n\n    10 ; trailing\n# own line\n    20\n"; "comments")]
+    fn format(input: &str) -> String {
+        format_source(input).expect("format error")
+    }
+
+    #[test]
+    fn idempotent() {
+        let sample = std::fs::read_to_string("../sample_program.ppr").unwrap();
+        let once = format_source(&sample).expect("format error");
+        let twice = format_source(&once).expect("format error");
+        assert_eq!(once, twice);
+    }
+}
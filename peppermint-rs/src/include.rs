@@ -0,0 +1,237 @@
+//! Splicing included files into a token stream.
+//!
+//! `.include "path"` is replaced by the tokens of the file at `path`, which is looked for next to
+//! the including file first and then in each include path in turn.
+//! Every file is added to a [`SourceMap`], and its tokens get spans into the joined text.
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use super::{
+    error::{Error, Span},
+    lex::{self, Token, Tokens},
+    source::SourceMap,
+};
+
+/// Tokens of a program and every file it includes.
+pub(crate) struct Loaded {
+    pub tokens: Tokens,
+    /// Errors from the lexer, which are reported in place of the parsing errors they cause.
+    pub lex_errors: Vec<Error>,
+    /// Errors in `.include` directives.
+    pub errors: Vec<Error>,
+}
+
+/// Remove `.` and `..` from a path without touching the file system, so that different
+/// spellings of a path compare equal.
+fn normalise(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Take the path of an `.include`, returning it with the span of the whole directive.
+fn take_path<I: Iterator<Item = (Token, Span)>>(
+    stream: &mut std::iter::Peekable<I>,
+    directive: Span,
+) -> Result<(String, Span), Error> {
+    match stream.next_if(|(tok, _)| matches!(tok, Token::Str(_))) {
+        Some((Token::Str(path), span)) => Ok((path, directive.start..span.end)),
+        _ => Err(Error::MissingArgument {
+            directive,
+            usage: ".include \"path\"",
+        }),
+    }
+}
+
+struct Loader<'a, F> {
+    sources: &'a mut SourceMap,
+    include_paths: &'a [PathBuf],
+    read: F,
+    /// Files currently being loaded, outermost first.
+    stack: Vec<PathBuf>,
+    loaded: Loaded,
+}
+
+impl<F: FnMut(&Path) -> io::Result<String>> Loader<'_, F> {
+    /// Add a file to the map and splice its tokens, and those of its includes, into the output.
+    fn load(&mut self, name: String, path: PathBuf, text: &str) {
+        let file = self.sources.add(name, text);
+        let offset = self.sources.files()[file].span.start;
+        let shift = |span: Span| span.start + offset..span.end + offset;
+
+        let lexed = lex::tokenise_recovering(text);
+        self.loaded.lex_errors.extend(
+            lexed
+                .errors
+                .into_iter()
+                .map(|e| e.map_spans(&mut |span| shift(span))),
+        );
+        self.stack.push(path);
+        let mut stream = lexed
+            .tokens
+            .into_iter()
+            .map(|(tok, span)| (tok, shift(span)))
+            .peekable();
+        while let Some((tok, span)) = stream.next() {
+            if !matches!(&tok, Token::Directive(d) if d == "include") {
+                self.loaded.tokens.push((tok, span));
+                continue;
+            }
+            match take_path(&mut stream, span) {
+                Ok((included, directive)) => self.include(&included, directive),
+                Err(e) => self.loaded.errors.push(e),
+            }
+        }
+        self.stack.pop();
+    }
+
+    fn include(&mut self, included: &str, directive: Span) {
+        let dir = self
+            .stack
+            .last()
+            .and_then(|path| path.parent())
+            .map(Path::to_owned)
+            .unwrap_or_default();
+        let candidates: Vec<_> = std::iter::once(dir)
+            .chain(self.include_paths.iter().cloned())
+            .map(|dir| normalise(&dir.join(included)))
+            .collect();
+        for candidate in candidates {
+            if self.stack.contains(&candidate) {
+                self.loaded.errors.push(Error::IncludeCycle { directive });
+                return;
+            }
+            if let Ok(text) = (self.read)(&candidate) {
+                self.load(candidate.display().to_string(), candidate, &text);
+                return;
+            }
+        }
+        self.loaded.errors.push(Error::IncludeNotFound {
+            directive,
+            path: included.to_owned(),
+        });
+    }
+}
+
+/// Load a file called `name` and every file it includes into `sources`.
+///
+/// Files are read with `read`.
+pub(crate) fn load(
+    sources: &mut SourceMap,
+    name: &str,
+    text: &str,
+    include_paths: &[PathBuf],
+    read: impl FnMut(&Path) -> io::Result<String>,
+) -> Loaded {
+    let mut loader = Loader {
+        sources,
+        include_paths,
+        read,
+        stack: Vec::new(),
+        loaded: Loaded {
+            tokens: Vec::new(),
+            lex_errors: Vec::new(),
+            errors: Vec::new(),
+        },
+    };
+    loader.load(name.to_owned(), normalise(Path::new(name)), text);
+    loader.loaded
+}
+
+/// Separate `.include` directives from a token stream, without loading anything.
+///
+/// Returns the remaining tokens and the canonical text of each directive.
+pub(crate) fn split_includes(tokens: Tokens) -> Result<(Tokens, Vec<(String, Span)>), Error> {
+    let mut includes = Vec::new();
+    let mut rest = Vec::new();
+    let mut stream = tokens.into_iter().peekable();
+    while let Some((tok, span)) = stream.next() {
+        if matches!(&tok, Token::Directive(d) if d == "include") {
+            let (path, directive) = take_path(&mut stream, span)?;
+            includes.push((format!(".include \"{path}\""), directive));
+        } else {
+            rest.push((tok, span));
+        }
+    }
+    Ok((rest, includes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{target::TargetSpec, Instruction::*, Program, Statement, Statement::*};
+    use test_case::test_case;
+
+    /// Parse `main.ppr` from a set of in-memory files, with `inc` as the include path.
+    fn parse(files: &[(&str, &str)]) -> (SourceMap, Result<Program, Vec<Error>>) {
+        let read = |path: &Path| {
+            files
+                .iter()
+                .find(|(name, _)| Path::new(name) == path)
+                .map(|(_, text)| (*text).to_owned())
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        };
+        let mut sources = SourceMap::default();
+        let main = files[0].1;
+        let result = Program::parse_sources(
+            &mut sources,
+            "src/main.ppr",
+            main,
+            &[PathBuf::from("inc")],
+            &TargetSpec::default(),
+            false,
+            read,
+        );
+        (sources, result)
+    }
+
+    #[test_case(&[
+        ("src/main.ppr", ".include \"util.ppr\"\nSTORE [0x10]"),
+        ("src/util.ppr", "10"),
+    ] => vec![Literal(10), InstrLine(Store(0x10))]; "next to file")]
+    #[test_case(&[
+        ("src/main.ppr", ".include \"math/lib.ppr\"\nJUMP :double"),
+        ("inc/math/lib.ppr", "double: .include \"../common.ppr\""),
+        ("inc/common.ppr", "ADD [0x10]"),
+    ] => vec![Label("double".to_owned()), InstrLine(Add(0x10)), InstrLine(Jump(0))]; "include path")]
+    fn include(files: &[(&str, &str)]) -> Vec<Statement<usize>> {
+        parse(files).1.expect("parse error").statements().to_vec()
+    }
+
+    #[test]
+    fn errors_in_included_file() {
+        let (sources, result) = parse(&[
+            ("src/main.ppr", "10\n.include \"lib.ppr\""),
+            ("src/lib.ppr", "JUMP :nowhere"),
+        ]);
+        let errors = result.expect_err("no error thrown");
+        let (file, span) = sources.local(&errors[0].covering_span());
+        assert_eq!(sources.name(file), "src/lib.ppr");
+        assert_eq!(span, 5..13);
+    }
+
+    #[test_case(&[("src/main.ppr", ".include \"missing.ppr\"")] => matches Error::IncludeNotFound {
+        directive: std::ops::Range { start: 0, end: 22 },
+        ..
+    }; "not found")]
+    #[test_case(&[
+        ("src/main.ppr", ".include \"a.ppr\""),
+        ("src/a.ppr", ".include \"./main.ppr\""),
+    ] => matches Error::IncludeCycle { .. }; "cycle")]
+    fn include_error(files: &[(&str, &str)]) -> Error {
+        parse(files).1.expect_err("no error thrown").remove(0)
+    }
+}
@@ -0,0 +1,243 @@
+//! Label names and the scopes jumps look them up in.
+//!
+//! Labels starting with a `.`, like `.loop:`, are local to the global label before them, so every
+//! routine can have its own `.loop`.
+//! Numeric labels, like `1:`, can be defined any number of times, and `:1f` jumps to the next one
+//! after the jump while `:1b` jumps to the last one before it.
+//!
+//! Every label is stored under a qualified name which is unique in the program, and jumps are
+//! rewritten to the qualified name of their target while the program is parsed in order.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    error::{Error, LabelScope, Span},
+    macros::EXPANSION_SEPARATOR,
+    suggest_name, Instruction, SpannedStatement, StatNum, Statement,
+};
+
+/// Whether a label name is a number, like `1`.
+pub(crate) fn is_numeric(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Name for a label synthesised at `index`, like `L0004`, which isn't already in `taken`.
+///
+/// The name is added to `taken`, so later synthesised names don't clash with it either.
+pub(crate) fn synthesise_name(index: usize, taken: &mut HashSet<String>) -> String {
+    let mut name = format!("L{index:04}");
+    while taken.contains(&name) {
+        name.push('_');
+    }
+    taken.insert(name.clone());
+    name
+}
+
+/// Every label in a program, by qualified name.
+#[derive(Default)]
+pub(crate) struct LabelTable {
+    /// Statement number and span of each label.
+    labels: HashMap<String, (StatNum, Span)>,
+    /// Scope of each reference to a local or numeric label.
+    references: HashMap<String, LabelScope>,
+    /// Global label the statements being parsed are local to.
+    scope: Option<String>,
+    /// Number of times each numeric label has been defined so far.
+    numeric: HashMap<String, usize>,
+}
+
+impl LabelTable {
+    /// Define a label at statement `stat`.
+    ///
+    /// A global label starts a new scope for the local labels after it.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::DuplicateLabel`] if the label is already defined in its scope.
+    pub(crate) fn define(&mut self, name: &str, stat: StatNum, span: Span) -> Result<(), Error> {
+        let qualified = if is_numeric(name) {
+            let count = self.numeric.entry(name.to_owned()).or_default();
+            *count += 1;
+            // numbered from 0, so the next definition after a jump has the count at the jump
+            format!("{name}:{}", *count - 1)
+        } else if name.starts_with('.') {
+            format!("{}{name}", self.scope.as_deref().unwrap_or_default())
+        } else {
+            // labels from macro bodies don't take over the scope of the code around them
+            if !name.contains(EXPANSION_SEPARATOR) {
+                self.scope = Some(name.to_owned());
+            }
+            name.to_owned()
+        };
+        if let Some((_, prev)) = self.labels.get(&qualified) {
+            return Err(Error::DuplicateLabel {
+                prev: prev.clone(),
+                this: span,
+            });
+        }
+        self.labels.insert(qualified, (stat, span));
+        Ok(())
+    }
+
+    /// Qualified name of the label a jump at the current position refers to.
+    fn qualify(&mut self, name: &str) -> String {
+        let (qualified, scope) =
+            if let Some(number) = name.strip_suffix('f').filter(|n| is_numeric(n)) {
+                let count = self.numeric.get(number).copied().unwrap_or_default();
+                (format!("{number}:{count}"), LabelScope::Forward)
+            } else if let Some(number) = name.strip_suffix('b').filter(|n| is_numeric(n)) {
+                let count = self.numeric.get(number).copied().unwrap_or_default();
+                // no definition is numbered `n:` if there's none before the jump
+                let last = count.checked_sub(1).map(|c| c.to_string());
+                (
+                    format!("{number}:{}", last.unwrap_or_default()),
+                    LabelScope::Backward,
+                )
+            } else if name.starts_with('.') {
+                let scope = self.scope.clone();
+                (
+                    format!("{}{name}", scope.as_deref().unwrap_or_default()),
+                    LabelScope::Local(scope),
+                )
+            } else {
+                return name.to_owned();
+            };
+        self.references.insert(qualified.clone(), scope);
+        qualified
+    }
+
+    /// Define the label of a label statement, or qualify the target of a jump.
+    ///
+    /// Statements have to be visited in order, since scopes depend on the labels before them.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::DuplicateLabel`] if a label is already defined in its scope.
+    pub(crate) fn visit(
+        &mut self,
+        stat: &mut SpannedStatement,
+        index: StatNum,
+    ) -> Result<(), Error> {
+        match &mut stat.statement {
+            Statement::Label(name) => self.define(name, index, stat.span.clone()),
+            Statement::InstrLine(
+                Instruction::Jump(name) | Instruction::JumpZ(name) | Instruction::JumpN(name),
+            ) => {
+                *name = self.qualify(name);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Find the statement of a label by its qualified name.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::UndefinedLabel`] if there's no such label, pointing at `reference`.
+    pub(crate) fn lookup(&self, qualified: &str, reference: Span) -> Result<StatNum, Error> {
+        if let Some((stat, _)) = self.labels.get(qualified) {
+            return Ok(*stat);
+        }
+        let scope = self
+            .references
+            .get(qualified)
+            .cloned()
+            .unwrap_or(LabelScope::Global);
+        Err(Error::UndefinedLabel {
+            reference,
+            suggestion: self.suggest(qualified, &scope),
+            scope,
+        })
+    }
+
+    /// Find a label in reach of a failed lookup which was probably meant instead.
+    fn suggest(&self, qualified: &str, scope: &LabelScope) -> Option<String> {
+        match scope {
+            LabelScope::Global => suggest_name(
+                qualified,
+                self.labels.keys().filter(|name| !name.contains(['.', ':'])),
+            ),
+            LabelScope::Local(global) => {
+                let prefix = global.as_deref().unwrap_or_default();
+                let locals: Vec<_> = self
+                    .labels
+                    .keys()
+                    .filter_map(|name| name.strip_prefix(prefix))
+                    .filter(|name| name.starts_with('.'))
+                    .map(str::to_owned)
+                    .collect();
+                suggest_name(&qualified[prefix.len()..], locals.iter())
+            }
+            // the label might just be on the other side of the jump
+            LabelScope::Backward | LabelScope::Forward => {
+                let (number, _) = qualified.split_once(':')?;
+                let count = self.numeric.get(number).copied().unwrap_or_default();
+                match scope {
+                    LabelScope::Backward => (count > 0).then(|| format!("{number}f")),
+                    _ => (count > 0).then(|| format!("{number}b")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Error, error::LabelScope, Instruction::*, Program, Statement::*};
+    use test_case::test_case;
+
+    #[test_case("f: .loop: JUMP :.loop g: .loop: JUMP :.loop" => vec![
+        Label("f".to_owned()),
+        Label(".loop".to_owned()),
+        InstrLine(Jump(1)),
+        Label("g".to_owned()),
+        Label(".loop".to_owned()),
+        InstrLine(Jump(4)),
+    ]; "local")]
+    #[test_case(".start: JUMP :.start" => vec![Label(".start".to_owned()), InstrLine(Jump(0))]; "local before global")]
+    #[test_case("1: JUMP :1f JUMP :1b 1: 10" => vec![
+        Label("1".to_owned()),
+        InstrLine(Jump(3)),
+        InstrLine(Jump(0)),
+        Label("1".to_owned()),
+        Literal(10),
+    ]; "numeric")]
+    fn labels(input: &str) -> Vec<crate::Statement<usize>> {
+        Program::parse_source(input)
+            .expect("parse error")
+            .statements()
+            .to_vec()
+    }
+
+    #[test_case("f: .loop: 10 g: JUMP :.lop" => Error::UndefinedLabel {
+        reference: 21..26,
+        suggestion: None,
+        scope: LabelScope::Local(Some("g".to_owned())),
+    }; "other scope")]
+    #[test_case("f: .loop: 10 JUMP :.lop" => Error::UndefinedLabel {
+        reference: 18..23,
+        suggestion: Some(".loop".to_owned()),
+        scope: LabelScope::Local(Some("f".to_owned())),
+    }; "local typo")]
+    #[test_case("JUMP :1b 1: 10" => Error::UndefinedLabel {
+        reference: 5..8,
+        suggestion: Some("1f".to_owned()),
+        scope: LabelScope::Backward,
+    }; "backward")]
+    #[test_case("JUMP :2f" => Error::UndefinedLabel {
+        reference: 5..8,
+        suggestion: None,
+        scope: LabelScope::Forward,
+    }; "forward")]
+    #[test_case("f: .a: 10 .a: 20" => Error::DuplicateLabel { prev: 3..6, this: 10..13 }; "duplicate local")]
+    fn label_error(input: &str) -> Error {
+        Program::parse_source(input).expect_err("no error thrown")
+    }
+
+    #[test_case("f:\n.loop:\n    10\n    JUMP :.loop\n1:\n    JUMP :1b\n    JUMP :1f\n1:\n"; "round trip")]
+    fn display(input: &str) {
+        let program = Program::parse_source(input).expect("parse error");
+        assert_eq!(program.to_string(), input);
+    }
+}
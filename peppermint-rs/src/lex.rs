@@ -1,9 +1,6 @@
-use super::{
-    error::{Error, Span},
-    Address, Literal,
-};
+use super::error::{Error, Span};
 
-use logos::Logos;
+use logos::{Logos, Skip};
 use num_traits::Num;
 
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -14,20 +11,50 @@ pub(crate) enum LexError {
     UnknownInst,
 }
 
+/// Integer as written in source, before it's checked against the target.
+///
+/// Wider than any target's addresses and literals, so a value which is too large is reported as
+/// out of range for the target rather than as a malformed integer.
+pub(crate) type Wide = u32;
+
 /// One [lexical token](https://en.wikipedia.org/wiki/Lexical_token#Lexical_token_and_lexical_tokenization) in Peppermint.
 #[derive(Logos, Debug, Clone, PartialEq)]
 #[logos(skip r"[ \t\n\f]+")]
-#[logos(skip r"[;#][^\n]*")] // skip comments
 #[logos(error = LexError)]
+#[logos(extras = Vec<Span>)]
 pub(crate) enum Token {
+    /// Comment, never emitted.
+    ///
+    /// Comments are skipped over like whitespace, but their spans are kept in the lexer's extras
+    /// for anything which needs to preserve them.
+    #[regex(r"[;#][^\n]*", |lex| {
+        lex.extras.push(lex.span());
+        Skip
+    })]
+    Comment,
     /// Instruction opcode.
+    ///
+    /// Words which aren't opcodes are turned into [`Token::Ident`] by [`tokenise_recovering`],
+    /// as are extended opcodes by [`demote_extended`] on targets without them.
     #[regex(
-        r"[A-Za-z]+",
+        r"[a-zA-Z][a-zA-Z_\-0-9]*",
         // use the strum::FromStr implementation
         |lex| lex.slice().parse().map_err(|_| LexError::UnknownInst),
         priority = 100,
     )]
     Instruction(InstructionKind),
+    /// Name which isn't an opcode, such as a symbol.
+    Ident(String),
+    /// Assembler directive, like `.equ`.
+    #[regex(r"\.[a-zA-Z]+", |lex| lex.slice()[1..].to_owned())]
+    Directive(String),
+    /// Symbolic address with an optional offset, like `[counter]` or `[table+2]`.
+    #[regex(
+        r"\[[a-zA-Z][a-zA-Z_\-0-9]*(\+(0[xb])?[0-9A-Za-z]+)?\]",
+        |lex| parse_symbol(debracket(lex.slice())),
+        priority = 10,
+    )]
+    Symbol((String, Wide)),
     /// Address literal.
     // NOTE: we could set different regex definitions for the different
     // bases, but if we do it this way then we ensure we get an integer parse error
@@ -36,52 +63,110 @@ pub(crate) enum Token {
     // unknown token, but the user probably meant for that to be a integer so we can
     // get better error reporting if we assume that.
     #[regex(r"\[(0[xb])?[0-9A-Za-z]+\]", |lex| parse_int(debracket(lex.slice())))]
-    Address(Address),
+    Address(Wide),
     /// Integer literal.
     #[regex(r"(0[xb])?[0-9A-Za-z]+", |lex| parse_int(lex.slice()))]
-    Literal(Literal),
+    Literal(Wide),
     /// Target label for a jump instruction.
-    #[regex(r":[a-zA-Z][a-zA-Z_\-0-9]*", |lex| lex.slice()[1..].to_string())]
+    ///
+    /// Local labels keep their leading `.`, and numeric labels keep their `f` or `b` suffix.
+    #[regex(r":\.?[a-zA-Z][a-zA-Z_\-0-9]*", |lex| lex.slice()[1..].to_string())]
+    #[regex(r":[0-9]+[fb]", |lex| lex.slice()[1..].to_string())]
     JumpLabel(String),
-    /// Label.
-    #[regex(r"[a-zA-Z][a-zA-Z_\-0-9]*:", |lex| {
+    /// Label, which is local if it starts with a `.` or is a number.
+    #[regex(r"\.?[a-zA-Z][a-zA-Z_\-0-9]*:|[0-9]+:", |lex| {
         let slice = lex.slice();
         // remove the ":"
         slice[0..(slice.len() - 1)].to_string()
     })]
     Label(String),
+    /// Quoted string, like the path in `.include "lib/math.ppr"`.
+    #[regex(r#""[^"\n]*""#, |lex| debracket(lex.slice()).to_owned())]
+    Str(String),
 }
 
 fn debracket(input: &str) -> &str {
     &input[1..(input.len() - 1)]
 }
 
+/// Split a symbolic address into its name and offset.
+fn parse_symbol(raw: &str) -> Result<(String, Wide), LexError> {
+    match raw.split_once('+') {
+        Some((name, offset)) => Ok((name.to_owned(), parse_int(offset)?)),
+        None => Ok((raw.to_owned(), 0)),
+    }
+}
+
 // only reuturns `ErrorKind` because the lexer can attach the span for us later
 fn parse_int<I: Num>(raw: &str) -> Result<I, LexError> {
-    let (raw, radix) = match raw.as_bytes().get(1) {
-        Some(b'x') => (&raw[2..], 16),
-        Some(b'b') => (&raw[2..], 2),
-        _ => (raw, 10),
-    };
-    I::from_str_radix(raw, radix).map_err(|_| LexError::InvalidInt)
+    super::parse_int(raw).ok_or(LexError::InvalidInt)
+}
+
+/// Tokens with their spans, in source order.
+pub(crate) type Tokens = Vec<(Token, Span)>;
+
+/// Output of the lexer when it carries on past errors.
+pub(crate) struct Lexed {
+    /// Every valid token in the source.
+    pub tokens: Tokens,
+    /// Spans of all comments.
+    pub comments: Vec<Span>,
+    /// Every error encountered, invalid tokens are left out of [`Self::tokens`].
+    pub errors: Vec<Error>,
 }
 
 /// Tokenise a source code string.
-pub(crate) fn tokenise(input: &str) -> Result<Vec<(Token, Span)>, Error> {
-    Token::lexer(input)
-        .spanned()
-        .map(|(res, span)| match res {
-            Ok(tok) => Ok((tok, span)),
-            Err(LexError::InvalidInt) => Err(Error::MalformedInteger { token: span }),
-            Err(LexError::InvalidToken) => Err(Error::InvalidToken { token: span }),
-            Err(LexError::UnknownInst) => Err(Error::UnknownInstruction { token: span }),
+#[cfg(test)]
+pub(crate) fn tokenise(input: &str) -> Result<Tokens, Vec<Error>> {
+    let lexed = tokenise_recovering(input);
+    if lexed.errors.is_empty() {
+        Ok(lexed.tokens)
+    } else {
+        Err(lexed.errors)
+    }
+}
+
+/// Turn the opcodes of [`InstructionSet::Extended`](crate::target::InstructionSet) back into
+/// plain names, for targets which don't have them.
+pub(crate) fn demote_extended(tokens: Tokens, source: &str) -> Tokens {
+    tokens
+        .into_iter()
+        .map(|(tok, span)| match tok {
+            Token::Instruction(kind) if kind.is_extended() => {
+                (Token::Ident(source[span.clone()].to_owned()), span)
+            }
+            tok => (tok, span),
         })
         .collect()
 }
 
+/// Tokenise a source code string, skipping over invalid tokens.
+pub(crate) fn tokenise_recovering(input: &str) -> Lexed {
+    let mut lexer = Token::lexer(input);
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(res) = lexer.next() {
+        let span = lexer.span();
+        match res {
+            Ok(tok) => tokens.push((tok, span)),
+            Err(LexError::InvalidInt) => errors.push(Error::MalformedInteger { token: span }),
+            Err(LexError::InvalidToken) => errors.push(Error::InvalidToken { token: span }),
+            Err(LexError::UnknownInst) => {
+                tokens.push((Token::Ident(lexer.slice().to_owned()), span));
+            }
+        }
+    }
+
+    Lexed {
+        tokens,
+        comments: lexer.extras,
+        errors,
+    }
+}
+
 /// Kind of instruction opcode.
-#[derive(Debug, Clone, strum::EnumString, PartialEq, Eq)]
-#[strum(ascii_case_insensitive)]
+#[derive(Debug, Clone, strum::EnumString, strum::VariantNames, PartialEq, Eq)]
+#[strum(ascii_case_insensitive, serialize_all = "UPPERCASE")]
 #[allow(missing_docs)]
 pub(crate) enum InstructionKind {
     Load,
@@ -92,6 +177,17 @@ pub(crate) enum InstructionKind {
     Sub,
     Store,
     Jump,
+    JumpZ,
+    JumpN,
+    Halt,
+}
+
+impl InstructionKind {
+    /// Whether this opcode is only on targets with
+    /// [`InstructionSet::Extended`](crate::target::InstructionSet).
+    pub(crate) fn is_extended(&self) -> bool {
+        matches!(self, Self::JumpZ | Self::JumpN | Self::Halt)
+    }
 }
 
 #[cfg(test)]
@@ -104,15 +200,26 @@ mod tests {
 
     #[test_case("load" => Instruction(Load))]
     #[test_case("lOaD" => Instruction(Load); "mixed case instr")]
+    #[test_case("JumpZ" => Instruction(JumpZ))]
+    #[test_case("halt" => Instruction(Halt))]
     #[test_case("[0x10]" => Address(16))]
     #[test_case("[12]" => Address(12))]
     #[test_case("[0b10]" => Address(2))]
+    #[test_case("[0x29ab18]" => Address(0x29_ab18); "address too large for any target")]
     #[test_case("0x1A" => Literal(26))]
     #[test_case("0x2a" => Literal(42))]
     #[test_case("0b1110" => Literal(14))]
     #[test_case("1120" => Literal(1120))]
     #[test_case("my10th-label:" => Label("my10th-label".to_owned()); "label")]
     #[test_case("my10th-LABEL:" => Label("my10th-LABEL".to_owned()); "label case sensitive")]
+    #[test_case(".loop:" => Label(".loop".to_owned()); "local label")]
+    #[test_case("10:" => Label("10".to_owned()); "numeric label")]
+    #[test_case(":.loop" => JumpLabel(".loop".to_owned()); "local jump label")]
+    #[test_case(":1f" => JumpLabel("1f".to_owned()); "numeric jump label")]
+    #[test_case(".equ" => Directive("equ".to_owned()))]
+    #[test_case("\"lib/math.ppr\"" => Str("lib/math.ppr".to_owned()))]
+    #[test_case("[counter]" => Symbol(("counter".to_owned(), 0)))]
+    #[test_case("[table+0x2]" => Symbol(("table".to_owned(), 2)); "symbol with offset")]
     fn single_token_lex(input: &str) -> Token {
         let mut lexer = Token::lexer(input);
         lexer.next().expect("no output").expect("lexing error")
@@ -137,7 +244,19 @@ mod tests {
             .collect()
     }
 
-    #[test_case("0x1bababab" => LexError::InvalidInt)]
+    #[test_case("; a comment\nLOAD 10 # another" => vec![0..11, 20..29])]
+    #[test_case("LOAD 10" => Vec::<Span>::new(); "no comments")]
+    fn comment_spans(input: &str) -> Vec<Span> {
+        tokenise_recovering(input).comments
+    }
+
+    #[test_case("LOAD [0xZZ] 10 $" => 2)]
+    #[test_case("LOAD [0x10]" => 0)]
+    fn error_count(input: &str) -> usize {
+        tokenise_recovering(input).errors.len()
+    }
+
+    #[test_case("0x1babababab" => LexError::InvalidInt)]
     #[test_case("0b1a" => LexError::InvalidInt)]
     #[test_case("10ab" => LexError::InvalidInt)]
     #[test_case("aslkdajns" => LexError::UnknownInst)]
@@ -146,7 +265,6 @@ mod tests {
         lexer
             .next()
             .expect("end of input")
-            .err()
-            .expect("lexer didn't throw an error")
+            .expect_err("lexer didn't throw an error")
     }
 }
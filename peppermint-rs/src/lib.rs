@@ -4,13 +4,35 @@
 #![deny(missing_docs)]
 #![allow(clippy::wildcard_imports)]
 
-use std::collections::{hash_map::Entry, HashMap};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, io,
+    iter::Peekable,
+    path::{Path, PathBuf},
+};
 
 mod lex;
-use lex::{InstructionKind, Token};
+use lex::{InstructionKind, Token, Wide};
+mod include;
+mod labels;
+mod macros;
+mod symbols;
+use labels::LabelTable;
+use object::Linkage;
+use symbols::{SymbolRef, SymbolTable};
 
+pub mod assemble;
+pub mod debug_info;
+pub mod diagnostic;
+pub mod disasm;
 pub mod error;
+pub mod format;
+pub mod object;
+pub mod source;
+pub mod target;
 use error::{Error, Span};
+use source::SourceMap;
+use target::{InstructionSet, TargetSpec};
 
 /// One memory word.
 pub type Word = u8;
@@ -24,6 +46,7 @@ type StatNum = usize;
 
 /// Statement in Peppermint.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(missing_docs)]
 pub enum Statement<L> {
     Label(String),
@@ -33,9 +56,10 @@ pub enum Statement<L> {
 
 /// Instruction w/ opcode in Peppermint.
 ///
-/// Generic over how the `jump` instruction refers to labels.
+/// Generic over how jump instructions refer to labels.
 /// This is to reduce code duplication between parsing and finalisation steps.
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(missing_docs)]
 pub enum Instruction<L> {
     Load(Address),
@@ -46,28 +70,279 @@ pub enum Instruction<L> {
     Sub(Address),
     Store(Address),
     Jump(L),
+    JumpZ(L),
+    JumpN(L),
+    Halt,
+}
+
+impl<L> Statement<L> {
+    /// Convert the label representation of any jump in this statement.
+    pub fn map_label<M>(self, f: impl FnOnce(L) -> M) -> Statement<M> {
+        match self {
+            Self::Label(name) => Statement::Label(name),
+            Self::InstrLine(ins) => Statement::InstrLine(ins.map_label(f)),
+            Self::Literal(l) => Statement::Literal(l),
+        }
+    }
+}
+
+impl<L> Instruction<L> {
+    /// Convert the label representation of this instruction if it's a jump.
+    pub fn map_label<M>(self, f: impl FnOnce(L) -> M) -> Instruction<M> {
+        match self {
+            Self::Load(a) => Instruction::Load(a),
+            Self::And(a) => Instruction::And(a),
+            Self::Xor(a) => Instruction::Xor(a),
+            Self::Or(a) => Instruction::Or(a),
+            Self::Add(a) => Instruction::Add(a),
+            Self::Sub(a) => Instruction::Sub(a),
+            Self::Store(a) => Instruction::Store(a),
+            Self::Jump(label) => Instruction::Jump(f(label)),
+            Self::JumpZ(label) => Instruction::JumpZ(f(label)),
+            Self::JumpN(label) => Instruction::JumpN(f(label)),
+            Self::Halt => Instruction::Halt,
+        }
+    }
+}
+
+impl<L> Instruction<L> {
+    /// Opcode of this instruction as written in source.
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Load(_) => "LOAD",
+            Self::And(_) => "AND",
+            Self::Xor(_) => "XOR",
+            Self::Or(_) => "OR",
+            Self::Add(_) => "ADD",
+            Self::Sub(_) => "SUB",
+            Self::Store(_) => "STORE",
+            Self::Jump(_) => "JUMP",
+            Self::JumpZ(_) => "JUMPZ",
+            Self::JumpN(_) => "JUMPN",
+            Self::Halt => "HALT",
+        }
+    }
+
+    /// Whether this instruction is only on targets with [`InstructionSet::Extended`].
+    #[must_use]
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Self::JumpZ(_) | Self::JumpN(_) | Self::Halt)
+    }
+}
+
+impl<L: fmt::Display> fmt::Display for Instruction<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(a)
+            | Self::And(a)
+            | Self::Xor(a)
+            | Self::Or(a)
+            | Self::Add(a)
+            | Self::Sub(a)
+            | Self::Store(a) => write!(f, "{} [0x{a:x}]", self.mnemonic()),
+            Self::Jump(label) | Self::JumpZ(label) | Self::JumpN(label) => {
+                write!(f, "{} :{label}", self.mnemonic())
+            }
+            Self::Halt => write!(f, "HALT"),
+        }
+    }
+}
+
+impl<L: fmt::Display> fmt::Display for Statement<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Label(name) => write!(f, "{name}:"),
+            Self::InstrLine(ins) => write!(f, "{ins}"),
+            Self::Literal(l) => write!(f, "{l}"),
+        }
+    }
+}
+
+/// Statement straight out of the parser, with the spans of its source.
+#[derive(Debug)]
+struct SpannedStatement {
+    statement: Statement<String>,
+    /// Span of the whole statement.
+    span: Span,
+    /// Span of the operand, if the statement is an instruction.
+    operand: Option<Span>,
+    /// Symbol giving the address or literal, which is a placeholder in `statement` until then.
+    symbol: Option<SymbolRef>,
+}
+
+/// Renders the statement as written, keeping symbolic operands.
+impl fmt::Display for SpannedStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.statement, &self.symbol) {
+            (Statement::InstrLine(ins), Some(symbol)) => {
+                write!(f, "{} [{symbol}]", ins.mnemonic())
+            }
+            (_, Some(symbol)) => write!(f, "{symbol}"),
+            (stat, None) => write!(f, "{stat}"),
+        }
+    }
+}
+
+/// Assembler directive, which doesn't produce a statement of its own.
+#[derive(Debug)]
+enum Directive {
+    /// `.equ name value`, binding a name to a constant.
+    Equ { name: String, value: DoubleWord },
+    /// `.word name [init]`, reserving a cell of RAM.
+    ///
+    /// The initial value is a literal after the name on the same line, so a literal on the next
+    /// line is a statement of its own.
+    Word {
+        name: String,
+        init: Option<DoubleWord>,
+    },
+    /// `.array name len`, reserving consecutive cells of RAM.
+    Array { name: String, len: Address },
+    /// `.global name`, exporting a label from an object.
+    Global { name: String },
+    /// `.extern name`, importing a label exported by another object.
+    Extern { name: String },
+}
+
+impl Directive {
+    /// Name of the symbol the directive defines.
+    fn name(&self) -> &str {
+        match self {
+            Self::Equ { name, .. }
+            | Self::Word { name, .. }
+            | Self::Array { name, .. }
+            | Self::Global { name }
+            | Self::Extern { name } => name,
+        }
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equ { name, value } => write!(f, ".equ {name} 0x{value:x}"),
+            Self::Word {
+                name,
+                init: Some(init),
+            } => write!(f, ".word {name} {init}"),
+            Self::Word { name, init: None } => write!(f, ".word {name}"),
+            Self::Array { name, len } => write!(f, ".array {name} {len}"),
+            Self::Global { name } => write!(f, ".global {name}"),
+            Self::Extern { name } => write!(f, ".extern {name}"),
+        }
+    }
+}
+
+/// Item straight out of the parser.
+#[derive(Debug)]
+enum Parsed {
+    Statement(SpannedStatement),
+    Directive(Directive, Span),
 }
 
 impl Statement<String> {
-    /// Take the next (labelled) statement from `stream`.
+    /// Address operand of this statement, if it's an instruction with one.
+    fn address(&self) -> Option<Address> {
+        match self {
+            Self::InstrLine(
+                Instruction::Load(a)
+                | Instruction::And(a)
+                | Instruction::Xor(a)
+                | Instruction::Or(a)
+                | Instruction::Add(a)
+                | Instruction::Sub(a)
+                | Instruction::Store(a),
+            ) => Some(*a),
+            _ => None,
+        }
+    }
+
+    /// Replace the address or literal of this statement with the value of a symbol.
+    fn set_value(&mut self, value: DoubleWord) {
+        match self {
+            Self::Literal(l)
+            | Self::InstrLine(
+                Instruction::Load(l)
+                | Instruction::And(l)
+                | Instruction::Xor(l)
+                | Instruction::Or(l)
+                | Instruction::Add(l)
+                | Instruction::Sub(l)
+                | Instruction::Store(l),
+            ) => *l = value,
+            Self::Label(_)
+            | Self::InstrLine(
+                Instruction::Jump(_)
+                | Instruction::JumpZ(_)
+                | Instruction::JumpN(_)
+                | Instruction::Halt,
+            ) => {}
+        }
+    }
+}
+
+impl Parsed {
+    /// Take the next (labelled) statement or directive from `stream`.
     ///
     /// Mutates `stream`, leaving everything after the next (valid) statement.
     ///
     /// # Errors
     ///
-    /// Can throw [`Error::EndOfFile`], [`Error::BadOperand`] or [`Error::InvalidToken]`.
-    fn take_from_token_stream(
-        stream: &mut impl Iterator<Item = (Token, Span)>,
-    ) -> Option<Result<(Self, Span), Error>> {
-        #[allow(clippy::enum_glob_use)]
-        use InstructionKind::*;
-
+    /// Can throw [`Error::EndOfFile`], [`Error::BadOperand`] or [`Error::InvalidToken]`, or
+    /// [`Error::AddressOutOfRange`] and [`Error::LiteralOutOfRange`] for values too large for any
+    /// `target`.
+    fn take_from_token_stream<I: Iterator<Item = (Token, Span)>>(
+        stream: &mut Peekable<I>,
+        same_line: &impl Fn(&Span, &Span) -> bool,
+        target: &TargetSpec,
+    ) -> Option<Result<Self, Error>> {
         let first_token = stream.next()?;
+        let statement = |statement, span, symbol| {
+            Some(Ok(Self::Statement(SpannedStatement {
+                statement,
+                span,
+                operand: None,
+                symbol,
+            })))
+        };
 
-        // handle labels and literals
+        // handle labels, literals and directives
         match first_token {
-            (Token::Label(name), span) => return Some(Ok((Self::Label(name), span))),
-            (Token::Literal(val), span) => return Some(Ok((Self::Literal(val), span))),
+            (Token::Label(name), span) => return statement(Statement::Label(name), span, None),
+            (Token::Literal(val), span) => {
+                return match narrow_literal(val, &span, target) {
+                    Ok(val) => statement(Statement::Literal(val), span, None),
+                    Err(e) => Some(Err(e)),
+                };
+            }
+            (Token::Directive(name), span) => {
+                return Some(Self::take_directive(&name, span, stream, same_line, target))
+            }
+            (Token::Ident(name), span) => {
+                // a name with an operand is most likely a misspelt instruction
+                if stream
+                    .next_if(|(tok, _)| {
+                        matches!(
+                            tok,
+                            Token::Address(_) | Token::Symbol(_) | Token::JumpLabel(_)
+                        )
+                    })
+                    .is_some()
+                {
+                    // opcodes are only names when the target doesn't have them
+                    return Some(Err(if name.parse::<InstructionKind>().is_ok() {
+                        Error::UnsupportedInstruction { instruction: span }
+                    } else {
+                        Error::UnknownInstruction { token: span }
+                    }));
+                }
+                // otherwise it's a literal given by a symbol
+                return statement(
+                    Statement::Literal(0),
+                    span,
+                    Some(SymbolRef { name, offset: 0 }),
+                );
+            }
             _ => {}
         }
 
@@ -79,45 +354,229 @@ impl Statement<String> {
                 token: first_token.1,
             }));
         };
+        Some(Self::take_instruction(opcode, opcode_span, stream, target))
+    }
+
+    /// Take the operand of an instruction from `stream`.
+    fn take_instruction<I: Iterator<Item = (Token, Span)>>(
+        opcode: InstructionKind,
+        opcode_span: Span,
+        stream: &mut Peekable<I>,
+        target: &TargetSpec,
+    ) -> Result<Self, Error> {
+        #[allow(clippy::enum_glob_use)]
+        use InstructionKind::*;
 
-        let Some((operand, operand_span)) = stream.next() else {
-            return Some(Err(Error::EndOfFile {
-                last_token: opcode_span,
+        if opcode == Halt {
+            return Ok(Self::Statement(SpannedStatement {
+                statement: Statement::InstrLine(Instruction::Halt),
+                span: opcode_span,
+                operand: None,
+                symbol: None,
             }));
+        }
+        let wanted = if matches!(opcode, Jump | JumpZ | JumpN) {
+            error::OperandType::Label
+        } else {
+            error::OperandType::Address
+        };
+
+        // a missing operand shouldn't swallow the start of the next statement
+        let Some((operand, operand_span)) = stream.next_if(|(tok, _)| {
+            !matches!(
+                tok,
+                Token::Instruction(_) | Token::Label(_) | Token::Ident(_) | Token::Directive(_)
+            )
+        }) else {
+            return Err(match stream.peek() {
+                Some((_, next_span)) => Error::BadOperand {
+                    opcode: opcode_span,
+                    operand: next_span.clone(),
+                    wanted,
+                },
+                None => Error::EndOfFile {
+                    last_token: opcode_span,
+                },
+            });
         };
         // construct the span of the full instruction
         let whole_span = (opcode_span.start)..(operand_span.end);
 
-        let full_inst = match (opcode, operand) {
-            (Load, Token::Address(addr)) => Instruction::Load(addr),
-            (And, Token::Address(addr)) => Instruction::And(addr),
-            (Xor, Token::Address(addr)) => Instruction::Xor(addr),
-            (Or, Token::Address(addr)) => Instruction::Or(addr),
-            (Add, Token::Address(addr)) => Instruction::Add(addr),
-            (Sub, Token::Address(addr)) => Instruction::Sub(addr),
-            (Store, Token::Address(addr)) => Instruction::Store(addr),
-            (Jump, Token::JumpLabel(value)) => Instruction::Jump(value),
-            // if it's a jump without a label
-            (Jump, Token::Address(_)) => {
-                return Some(Err(Error::BadOperand {
-                    opcode: opcode_span,
-                    operand: operand_span,
-                    wanted: error::OperandType::Label,
-                }))
-            }
+        let (addr, symbol) = match wanted {
+            error::OperandType::Address => address_operand(&operand, &operand_span, target)?,
+            error::OperandType::Label => (None, None),
+        };
+        let full_inst = match (opcode, addr, operand) {
+            (Load, Some(addr), _) => Instruction::Load(addr),
+            (And, Some(addr), _) => Instruction::And(addr),
+            (Xor, Some(addr), _) => Instruction::Xor(addr),
+            (Or, Some(addr), _) => Instruction::Or(addr),
+            (Add, Some(addr), _) => Instruction::Add(addr),
+            (Sub, Some(addr), _) => Instruction::Sub(addr),
+            (Store, Some(addr), _) => Instruction::Store(addr),
+            (Jump, _, Token::JumpLabel(value)) => Instruction::Jump(value),
+            (JumpZ, _, Token::JumpLabel(value)) => Instruction::JumpZ(value),
+            (JumpN, _, Token::JumpLabel(value)) => Instruction::JumpN(value),
+            // a jump without a label or an address instruction without an address
             _ => {
-                return Some(Err(Error::BadOperand {
+                return Err(Error::BadOperand {
                     opcode: opcode_span,
                     operand: operand_span,
-                    wanted: error::OperandType::Address,
-                }))
+                    wanted,
+                })
             }
         };
 
-        Some(Ok((Self::InstrLine(full_inst), whole_span)))
+        Ok(Self::Statement(SpannedStatement {
+            statement: Statement::InstrLine(full_inst),
+            span: whole_span,
+            operand: Some(operand_span),
+            symbol,
+        }))
+    }
+
+    /// Take the arguments of a directive from `stream`.
+    ///
+    /// The value of a `.word` is optional, so it's only taken from the same line as the name.
+    fn take_directive<I: Iterator<Item = (Token, Span)>>(
+        directive: &str,
+        span: Span,
+        stream: &mut Peekable<I>,
+        same_line: &impl Fn(&Span, &Span) -> bool,
+        target: &TargetSpec,
+    ) -> Result<Self, Error> {
+        let usage = match directive {
+            "equ" => ".equ name value",
+            "word" => ".word name [value]",
+            "array" => ".array name length",
+            "global" => ".global label",
+            "extern" => ".extern label",
+            _ => return Err(Error::UnknownDirective { token: span }),
+        };
+        let missing = |directive| Error::MissingArgument { directive, usage };
+
+        let Some((Token::Ident(name), name_span)) =
+            stream.next_if(|(tok, _)| matches!(tok, Token::Ident(_)))
+        else {
+            return Err(missing(span));
+        };
+        // a label is the only argument of linkage directives
+        let whole_span = span.start..name_span.end;
+        match directive {
+            "global" => return Ok(Self::Directive(Directive::Global { name }, whole_span)),
+            "extern" => return Ok(Self::Directive(Directive::Extern { name }, whole_span)),
+            _ => {}
+        }
+        let value = stream.next_if(|(tok, value_span)| {
+            (matches!(tok, Token::Literal(_))
+                || (directive == "equ" && matches!(tok, Token::Address(_))))
+                && (directive != "word" || same_line(&name_span, value_span))
+        });
+        let (value, end) = match value {
+            Some((Token::Literal(value), value_span)) => (
+                Some(narrow_literal(value, &value_span, target)?),
+                value_span.end,
+            ),
+            Some((Token::Address(value), value_span)) => (
+                Some(narrow_address(value, &value_span, target)?),
+                value_span.end,
+            ),
+            // only `.word` can leave out its value
+            _ if directive == "word" => (None, name_span.end),
+            _ => return Err(missing(span.start..name_span.end)),
+        };
+
+        let directive = match (directive, value) {
+            ("equ", Some(value)) => Directive::Equ { name, value },
+            ("array", Some(len)) => Directive::Array { name, len },
+            ("word", init) => Directive::Word { name, init },
+            _ => unreachable!("directive checked above"),
+        };
+        Ok(Self::Directive(directive, span.start..end))
+    }
+}
+
+/// Take the address from the operand of an address instruction, if it is one.
+///
+/// Symbolic addresses get a placeholder until symbols are resolved.
+fn address_operand(
+    operand: &Token,
+    span: &Span,
+    target: &TargetSpec,
+) -> Result<(Option<Address>, Option<SymbolRef>), Error> {
+    Ok(match operand {
+        Token::Address(addr) => (Some(narrow_address(*addr, span, target)?), None),
+        Token::Symbol((name, offset)) => (
+            Some(0),
+            Some(SymbolRef {
+                name: name.clone(),
+                offset: narrow_address(*offset, span, target)?,
+            }),
+        ),
+        _ => (None, None),
+    })
+}
+
+/// Narrow an address as it was lexed, which only fails if it's too large for any target.
+fn narrow_address(address: Wide, span: &Span, target: &TargetSpec) -> Result<Address, Error> {
+    Address::try_from(address).map_err(|_| Error::AddressOutOfRange {
+        address: span.clone(),
+        max: target.max_address(),
+    })
+}
+
+/// Narrow a literal as it was lexed, which only fails if it's too large for any target.
+fn narrow_literal(literal: Wide, span: &Span, target: &TargetSpec) -> Result<Literal, Error> {
+    Literal::try_from(literal).map_err(|_| Error::LiteralOutOfRange {
+        literal: span.clone(),
+        max: target.max_literal(),
+    })
+}
+
+/// Check that a statement is supported by the target and its operand is within its limits.
+fn check_target(
+    stat: &Statement<String>,
+    span: &Span,
+    operand: Option<&Span>,
+    target: &TargetSpec,
+) -> Result<(), Error> {
+    match stat {
+        Statement::Literal(l) if *l > target.max_literal() => Err(Error::LiteralOutOfRange {
+            literal: span.clone(),
+            max: target.max_literal(),
+        }),
+        Statement::InstrLine(
+            Instruction::Load(a)
+            | Instruction::And(a)
+            | Instruction::Xor(a)
+            | Instruction::Or(a)
+            | Instruction::Add(a)
+            | Instruction::Sub(a)
+            | Instruction::Store(a),
+        ) if *a > target.max_address() => Err(Error::AddressOutOfRange {
+            address: operand.unwrap_or(span).clone(),
+            max: target.max_address(),
+        }),
+        Statement::InstrLine(ins)
+            if ins.is_extended() && target.instruction_set == InstructionSet::Base =>
+        {
+            Err(Error::UnsupportedInstruction {
+                instruction: span.clone(),
+            })
+        }
+        _ => Ok(()),
     }
 }
 
+/// Find the candidate most similar to `name`, if any are similar enough to be a likely typo.
+fn suggest_name<S: AsRef<str>>(name: &str, candidates: impl Iterator<Item = S>) -> Option<String> {
+    candidates
+        .map(|label| (strsim::damerau_levenshtein(name, label.as_ref()), label))
+        .filter(|(distance, _)| *distance <= name.len() / 3 + 1)
+        .min_by(|(a, x), (b, y)| (a, x.as_ref()).cmp(&(b, y.as_ref())))
+        .map(|(_, label)| label.as_ref().to_owned())
+}
+
 /// Parsed and checked Peppermint program.
 ///
 /// Abstract Syntax Tree with labels finalised.
@@ -127,6 +586,76 @@ impl Statement<String> {
 #[derive(Debug)]
 pub struct Program {
     statements: Vec<Statement<StatNum>>,
+    /// Source span of each statement, empty if the program wasn't parsed from source.
+    spans: Vec<Span>,
+    /// Source span of the operand of each statement, parallel to `spans`.
+    operands: Vec<Option<Span>>,
+    /// Label statements exported with `.global`.
+    globals: Vec<StatNum>,
+    /// Label statements standing in for labels imported with `.extern`, which come after every
+    /// other statement.
+    externs: Vec<StatNum>,
+    /// Initial contents of memory, from the initial values of `.word`s.
+    memory: Vec<(Address, DoubleWord)>,
+}
+
+/// Renders the program as Peppermint source, one statement per line.
+///
+/// Jumps to a statement which isn't a label, as programs which weren't parsed can have, get a
+/// label synthesised in front of their target, named like `L0004` but unlike any other label.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut taken: HashSet<String> = self
+            .statements
+            .iter()
+            .filter_map(|stat| match stat {
+                Statement::Label(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut synthesised = BTreeMap::new();
+        for stat in &self.statements {
+            if let Statement::InstrLine(
+                Instruction::Jump(target) | Instruction::JumpZ(target) | Instruction::JumpN(target),
+            ) = stat
+            {
+                if !matches!(self.statements.get(*target), Some(Statement::Label(_))) {
+                    synthesised
+                        .entry(*target)
+                        .or_insert_with(|| labels::synthesise_name(*target, &mut taken));
+                }
+            }
+        }
+
+        for (i, stat) in self.statements.iter().enumerate() {
+            if let Some(name) = synthesised.get(&i) {
+                writeln!(f, "{name}:")?;
+            }
+            let stat = stat
+                .clone()
+                .map_label(|target| match self.statements.get(target) {
+                    // the nearest label with the number, since that's what the jump resolved to
+                    Some(Statement::Label(name)) if labels::is_numeric(name) => {
+                        format!("{name}{}", if target > i { 'f' } else { 'b' })
+                    }
+                    Some(Statement::Label(name)) => name.clone(),
+                    _ => synthesised[&target].clone(),
+                });
+            if matches!(stat, Statement::Label(_)) {
+                writeln!(f, "{stat}")?;
+            } else {
+                writeln!(f, "    {stat}")?;
+            }
+        }
+        // jumps past the end all halt, so their labels can all go at the end
+        for name in synthesised
+            .range(self.statements.len()..)
+            .map(|(_, name)| name)
+        {
+            writeln!(f, "{name}:")?;
+        }
+        Ok(())
+    }
 }
 
 impl Program {
@@ -136,65 +665,330 @@ impl Program {
         &self.statements
     }
 
+    /// Initial contents of memory cells, as `(address, value)` pairs, for the simulator to load
+    /// before the program starts.
+    ///
+    /// These are the initial values given to `.word`s, which aren't part of the machine code.
+    #[must_use]
+    pub fn initial_memory(&self) -> &[(Address, DoubleWord)] {
+        &self.memory
+    }
+
+    /// Span of the source code a statement was parsed from.
+    ///
+    /// Returns [`None`] if there's no such statement or the program wasn't parsed from source.
+    #[must_use]
+    pub fn span_of(&self, stat: StatNum) -> Option<Span> {
+        self.spans.get(stat).cloned()
+    }
+
+    /// Span of the source code of a statement's operand.
+    ///
+    /// Returns [`None`] if the statement has no operand, there's no such statement or the program
+    /// wasn't parsed from source.
+    #[must_use]
+    pub fn operand_span_of(&self, stat: StatNum) -> Option<Span> {
+        self.operands.get(stat).cloned().flatten()
+    }
+
+    /// Find the statement parsed from the source code at a byte offset.
+    ///
+    /// Returns [`None`] if the offset isn't inside a statement, such as in whitespace, a comment
+    /// or a directive.
+    #[must_use]
+    pub fn statement_at(&self, offset: usize) -> Option<StatNum> {
+        self.spans.iter().position(|span| span.contains(&offset))
+    }
+
     /// Parse a token stream and make the labels absolute.
-    fn from_tokens(stream: &mut impl Iterator<Item = (Token, Span)>) -> Result<Self, Error> {
-        use Instruction::*;
-        use Statement::*;
-        let stat_stream = std::iter::from_fn(|| Statement::take_from_token_stream(stream));
+    ///
+    /// Parsing carries on past malformed statements so that every error in the stream is reported.
+    /// If `object` is set, the stream is parsed as an [`object`].
+    fn from_tokens(
+        stream: &mut impl Iterator<Item = (Token, Span)>,
+        same_line: impl Fn(&Span, &Span) -> bool,
+        target: &TargetSpec,
+        object: bool,
+    ) -> Result<Self, Vec<Error>> {
+        let mut stream = stream.peekable();
+        let parsed_stream =
+            std::iter::from_fn(|| Parsed::take_from_token_stream(&mut stream, &same_line, target));
 
         let mut statements = Vec::new();
-        let mut labels: HashMap<String, (usize, Span)> = HashMap::new();
-        for (i, stat) in stat_stream.enumerate() {
-            let (stat, span) = stat?;
-            if let Statement::Label(name) = &stat {
-                // TODO: remove clone
-                match labels.entry(name.clone()) {
-                    ent @ Entry::Vacant(_) => {
-                        ent.or_insert((i, span));
-                    }
-                    Entry::Occupied(entry) => {
-                        let (_, prev_span) = entry.remove();
-                        return Err(Error::DuplicateLabel {
-                            prev: prev_span,
-                            this: span,
-                        });
+        let mut errors = Vec::new();
+        let mut labels = LabelTable::default();
+        let mut symbols = SymbolTable::default();
+        let mut linkage = Linkage::default();
+        for parsed in parsed_stream {
+            let mut stat = match parsed {
+                Ok(Parsed::Statement(stat)) => stat,
+                Ok(Parsed::Directive(directive, span)) => {
+                    let result = match directive {
+                        Directive::Global { .. } | Directive::Extern { .. } => {
+                            linkage.declare(directive, span, object)
+                        }
+                        Directive::Word { .. } | Directive::Array { .. } if object => {
+                            // still define the symbol, so its uses aren't undefined as well
+                            symbols
+                                .define(directive, span.clone())
+                                .and(Err(Error::ReservationInObject { directive: span }))
+                        }
+                        directive => symbols.define(directive, span),
+                    };
+                    if let Err(e) = result {
+                        errors.push(e);
                     }
+                    continue;
                 }
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            if let Err(e) = labels.visit(&mut stat, statements.len()) {
+                errors.push(e);
             }
             statements.push(stat);
         }
+        let externs = linkage.import(&mut statements, &mut labels, &mut errors);
+        let globals = linkage.export(&labels, &mut errors);
+
+        let memory = symbols.allocate(&statements, target).unwrap_or_else(|e| {
+            errors.push(e);
+            Vec::new()
+        });
+
+        // symbols can be used before they're defined, so values are only known now
+        for stat in &mut statements {
+            if let Err(e) = symbols.resolve(stat, target).and_then(|()| {
+                check_target(&stat.statement, &stat.span, stat.operand.as_ref(), target)
+            }) {
+                errors.push(e);
+            }
+        }
 
+        let mut spans = Vec::with_capacity(statements.len());
+        let mut operands = Vec::with_capacity(statements.len());
         let statements = statements
             .into_iter()
             .map(|stat| {
-                // TODO: this is ridiculous
-                match stat {
-                    InstrLine(Jump(name)) => InstrLine(Jump(labels[&name].0)),
-                    Literal(l) => Literal(l),
-                    InstrLine(Load(a)) => InstrLine(Load(a)),
-                    InstrLine(And(a)) => InstrLine(And(a)),
-                    InstrLine(Xor(a)) => InstrLine(Xor(a)),
-                    InstrLine(Or(a)) => InstrLine(Or(a)),
-                    InstrLine(Add(a)) => InstrLine(Add(a)),
-                    InstrLine(Sub(a)) => InstrLine(Sub(a)),
-                    InstrLine(Store(a)) => InstrLine(Store(a)),
-                    Label(name) => Label(name),
-                }
+                let SpannedStatement {
+                    statement: stat,
+                    span,
+                    operand,
+                    ..
+                } = stat;
+                let stat = stat.map_label(|name| {
+                    // jumps always have an operand
+                    let reference = operand.clone().unwrap_or(span.clone());
+                    match labels.lookup(&name, reference) {
+                        Ok(target) => target,
+                        Err(e) => {
+                            errors.push(e);
+                            // placeholder, the program is discarded anyway
+                            0
+                        }
+                    }
+                });
+                spans.push(span);
+                operands.push(operand);
+                stat
             })
             .collect();
 
-        Ok(Self { statements })
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(Self {
+            statements,
+            spans,
+            operands,
+            globals,
+            externs,
+            memory,
+        })
     }
 
     /// Fully parse source code into final syntax tree.
     ///
+    /// Only the first error in the source is reported; see [`Self::parse_source_recovering`] to
+    /// get all of them.
+    ///
     /// # Errors
     ///
     /// May throw any [`Error`] from any stage of parsing.
     pub fn parse_source(input: &str) -> Result<Self, Error> {
-        let tokens = lex::tokenise(input)?;
-        Program::from_tokens(&mut tokens.into_iter())
+        // there's always at least one error if parsing failed
+        Self::parse_source_recovering(input).map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Fully parse source code into final syntax tree, collecting every error.
+    ///
+    /// Errors are sorted by their position in the source.
+    /// To render them for the user, see [`diagnostic`].
+    ///
+    /// # Errors
+    ///
+    /// Returns every [`Error`] found in any stage of parsing.
+    pub fn parse_source_recovering(input: &str) -> Result<Self, Vec<Error>> {
+        Self::parse_source_for(input, &TargetSpec::default())
+    }
+
+    /// Fully parse source code for a specific target, collecting every error.
+    ///
+    /// Behaves like [`Self::parse_source_recovering`], but checks operands against the limits of
+    /// `target` instead of the default.
+    ///
+    /// # Errors
+    ///
+    /// Returns every [`Error`] found in any stage of parsing.
+    pub fn parse_source_for(input: &str, target: &TargetSpec) -> Result<Self, Vec<Error>> {
+        // there's no file system to include from
+        Self::parse_sources(
+            &mut SourceMap::default(),
+            "",
+            input,
+            &[],
+            target,
+            false,
+            |_| Err(io::ErrorKind::NotFound.into()),
+        )
+    }
+
+    /// Fully parse a file and every file it includes for a specific target, collecting every
+    /// error.
+    ///
+    /// Each `.include "path"` is looked up relative to the directory of the including file, then
+    /// relative to each of `include_paths` in turn.
+    /// `name` is the path of the file `input` was read from.
+    ///
+    /// The returned [`SourceMap`] holds the text of every file, which the spans in the program and
+    /// in errors point into.
+    pub fn parse_with_includes(
+        name: &str,
+        input: &str,
+        include_paths: &[PathBuf],
+        target: &TargetSpec,
+    ) -> (SourceMap, Result<Self, Vec<Error>>) {
+        let mut sources = SourceMap::default();
+        let result = Self::parse_sources(
+            &mut sources,
+            name,
+            input,
+            include_paths,
+            target,
+            false,
+            |path| std::fs::read_to_string(path),
+        );
+        (sources, result)
     }
+
+    /// Fully parse a file and every file it includes as an object, collecting every error.
+    ///
+    /// Behaves like [`Self::parse_with_includes`], but the program can import labels with
+    /// `.extern` and can't reserve memory.
+    /// To assemble and link the result, see [`object`].
+    pub fn parse_object(
+        name: &str,
+        input: &str,
+        include_paths: &[PathBuf],
+        target: &TargetSpec,
+    ) -> (SourceMap, Result<Self, Vec<Error>>) {
+        let mut sources = SourceMap::default();
+        let result = Self::parse_sources(
+            &mut sources,
+            name,
+            input,
+            include_paths,
+            target,
+            true,
+            |path| std::fs::read_to_string(path),
+        );
+        (sources, result)
+    }
+
+    /// Load, expand and parse a program, reading included files with `read`.
+    fn parse_sources(
+        sources: &mut SourceMap,
+        name: &str,
+        input: &str,
+        include_paths: &[PathBuf],
+        target: &TargetSpec,
+        object: bool,
+        read: impl FnMut(&Path) -> io::Result<String>,
+    ) -> Result<Self, Vec<Error>> {
+        let loaded = include::load(sources, name, input, include_paths, read);
+        // the extended opcodes are free to use as names on targets without them
+        let tokens = match target.instruction_set {
+            InstructionSet::Base => lex::demote_extended(loaded.tokens, sources.text()),
+            InstructionSet::Extended => loaded.tokens,
+        };
+        let (tokens, expansion) = macros::expand(tokens, sources.text());
+        let same_line =
+            |before: &Span, after: &Span| expansion.same_line(sources.text(), before, after);
+        let mut errors =
+            match Program::from_tokens(&mut tokens.into_iter(), same_line, target, object) {
+                Ok(program)
+                    if loaded.lex_errors.is_empty()
+                        && loaded.errors.is_empty()
+                        && expansion.errors.is_empty() =>
+                {
+                    return Ok(expansion.relocate(program))
+                }
+                Ok(_) => Vec::new(),
+                Err(errors) => errors.into_iter().map(|e| expansion.locate(e)).collect(),
+            };
+        errors.extend(loaded.errors);
+        errors.extend(expansion.errors);
+
+        // a statement containing a token the lexer rejected is bound to be malformed as well,
+        // only report the root cause
+        errors.retain(|e| {
+            let span = e.covering_span();
+            !loaded
+                .lex_errors
+                .iter()
+                .any(|lex_err| span.contains(&lex_err.covering_span().start))
+        });
+        errors.extend(loaded.lex_errors);
+        errors.sort_by_key(|e| e.covering_span().start);
+
+        Err(errors)
+    }
+
+    /// Reconstruct a program from a Tick Talk machine code image.
+    ///
+    /// See [`disasm`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`disasm::Error`] if the image isn't valid machine code.
+    pub fn from_machine_code(image: &[u8]) -> Result<Self, disasm::Error> {
+        disasm::disassemble(image)
+    }
+}
+
+/// Parse a decimal or `0x`/`0b` prefixed integer, like the numbers in source code.
+///
+/// Tools which take numbers from users should use this, so they accept the same numbers as the
+/// assembler.
+#[must_use]
+pub fn parse_int<I: num_traits::Num>(raw: &str) -> Option<I> {
+    let (digits, radix) = match raw.get(..2) {
+        Some("0x") => (&raw[2..], 16),
+        Some("0b") => (&raw[2..], 2),
+        _ => (raw, 10),
+    };
+    I::from_str_radix(digits, radix).ok()
+}
+
+/// Find the span of every comment in source code, including its leading `;` or `#`.
+///
+/// Comments aren't part of a [`Program`], but tools can give them meaning of their own.
+#[must_use]
+pub fn comments(input: &str) -> Vec<Span> {
+    lex::tokenise_recovering(input).comments
 }
 
 #[cfg(test)]
@@ -212,22 +1006,283 @@ mod tests {
         Literal(10),
         InstrLine(Jump("label".to_string()))
     ])]
+    #[test_case("jumpz :a JUMPN :b halt 10" => vec![
+        InstrLine(JumpZ("a".to_string())),
+        InstrLine(JumpN("b".to_string())),
+        InstrLine(Halt),
+        Literal(10),
+    ]; "extended")]
     fn statement_sequence_from_str(input: &str) -> Vec<Statement<String>> {
-        let mut stream = lex::tokenise(input).expect("lexer error").into_iter();
-        std::iter::from_fn(|| Statement::take_from_token_stream(&mut stream))
-            .map(|res| res.unwrap().0)
-            .collect()
+        let mut stream = lex::tokenise(input)
+            .expect("lexer error")
+            .into_iter()
+            .peekable();
+        let same_line = |before: &Span, after: &Span| macros::same_line(input, before, after);
+        std::iter::from_fn(|| {
+            Parsed::take_from_token_stream(&mut stream, &same_line, &TargetSpec::default())
+        })
+        .map(|res| match res.unwrap() {
+            Parsed::Statement(stat) => stat.statement,
+            Parsed::Directive(..) => panic!("unexpected directive"),
+        })
+        .collect()
     }
 
-    #[test_case("load :label" => matches Error::BadOperand { 
-        wanted: error::OperandType::Address, 
+    #[test_case("load :label" => matches Error::BadOperand {
+        wanted: error::OperandType::Address,
         ..
     })]
+    #[test_case("jumpz [0x10]" => matches Error::BadOperand {
+        wanted: error::OperandType::Label,
+        ..
+    }; "conditional jump to address")]
     fn statement_error_from_str(input: &str) -> Error {
-        let mut stream = lex::tokenise(input).expect("lexer error").into_iter();
-        let res = std::iter::from_fn(|| Statement::take_from_token_stream(&mut stream))
-            .collect::<Result<Vec<_>, _>>();
+        let mut stream = lex::tokenise(input)
+            .expect("lexer error")
+            .into_iter()
+            .peekable();
+        let same_line = |before: &Span, after: &Span| macros::same_line(input, before, after);
+        let res = std::iter::from_fn(|| {
+            Parsed::take_from_token_stream(&mut stream, &same_line, &TargetSpec::default())
+        })
+        .collect::<Result<Vec<_>, _>>();
+
+        res.expect_err("no error thrown")
+    }
+
+    #[test_case("LOAD [0xZZ]\nfoo 10\nSTORE :x" => 3; "one error per line")]
+    #[test_case("a: LOAD\nSTORE [0x10]\na: 10" => 2; "missing operand doesn't swallow next line")]
+    #[test_case("10 STORE [0x10]" => 0)]
+    fn recovered_error_count(input: &str) -> usize {
+        Program::parse_source_recovering(input)
+            .err()
+            .map_or(0, |errors| errors.len())
+    }
+
+    #[test_case("loop: JUMP :lopo" => matches Error::UndefinedLabel {
+        reference: std::ops::Range { start: 11, end: 16 },
+        suggestion: Some(ref s),
+        scope: error::LabelScope::Global,
+    } if s == "loop"; "typo")]
+    #[test_case("start: JUMP :something-else" => matches Error::UndefinedLabel {
+        suggestion: None,
+        ..
+    }; "nothing similar")]
+    #[test_case("JUMP :nowhere" => matches Error::UndefinedLabel {
+        reference: std::ops::Range { start: 5, end: 13 },
+        suggestion: None,
+        scope: error::LabelScope::Global,
+    }; "no labels")]
+    fn undefined_label(input: &str) -> Error {
+        Program::parse_source(input).expect_err("no error thrown")
+    }
 
-        res.err().expect("no error thrown")
+    #[test_case(".equ counter 0x20\nADD [counter]" => vec![InstrLine(Add(0x20))]; "address")]
+    #[test_case("STORE [table+2]\n.equ table [0x10]" => vec![InstrLine(Store(0x12))]; "forward reference with offset")]
+    #[test_case(".equ limit 100\nlimit" => vec![Literal(100)]; "literal")]
+    #[test_case(".word count 5\n.array buf 3\nLOAD [0x0]\nSTORE [count]\nADD [buf+1]" => vec![
+        InstrLine(Load(0)),
+        InstrLine(Store(1)),
+        InstrLine(Add(3)),
+    ]; "reserved memory")]
+    #[test_case(".word x 1\nloop: JUMP :loop" => vec![
+        Label("loop".to_owned()),
+        InstrLine(Jump(0)),
+    ]; "initial value adds no statements")]
+    #[test_case(".word count\n5\nSTORE [count]" => vec![
+        Literal(5),
+        InstrLine(Store(0)),
+    ]; "init on next line")]
+    #[test_case(".macro var n\n.word n\n.endm\nvar x\n5\nSTORE [x]" => vec![
+        Literal(5),
+        InstrLine(Store(0)),
+    ]; "init on next line after macro")]
+    fn symbols(input: &str) -> Vec<Statement<usize>> {
+        Program::parse_source(input)
+            .expect("parse error")
+            .statements()
+            .to_vec()
+    }
+
+    #[test_case(".word count 5\n.array buf 3\nLOAD [0x0]" => vec![(1, 5)]; "after direct address")]
+    #[test_case(".word a 1\n.word b\n.word c 0x7fff" => vec![(0, 1), (2, 0x7fff)]; "some without init")]
+    #[test_case(".word count\n5\nSTORE [count]" => Vec::<(Address, DoubleWord)>::new(); "init on next line")]
+    #[test_case(".macro var n v\n.word n v\n.endm\nvar x 5" => vec![(0, 5)]; "init from macro argument")]
+    fn initial_memory(input: &str) -> Vec<(Address, DoubleWord)> {
+        Program::parse_source(input)
+            .expect("parse error")
+            .initial_memory()
+            .to_vec()
+    }
+
+    #[test_case(".equ counter 0x20\nADD [countr]" => matches Error::UndefinedSymbol {
+        reference: std::ops::Range { start: 22, end: 30 },
+        suggestion: Some(ref s),
+    } if s == "counter"; "typo")]
+    #[test_case(".equ a 1\n.equ a 2" => matches Error::DuplicateSymbol {
+        prev: std::ops::Range { start: 0, end: 8 },
+        this: std::ops::Range { start: 9, end: 17 },
+    }; "duplicate")]
+    #[test_case("LAOD" => matches Error::UndefinedSymbol {
+        suggestion: Some(ref s),
+        ..
+    } if s == "LOAD"; "bare misspelt instruction")]
+    #[test_case("halt: 10\nhlt" => matches Error::UndefinedSymbol {
+        suggestion: Some(ref s),
+        ..
+    } if s == "HALT"; "opcode suggested over label")]
+    #[test_case(".equ count 1\ncont" => matches Error::UndefinedSymbol {
+        suggestion: Some(ref s),
+        ..
+    } if s == "count"; "bare misspelt symbol")]
+    #[test_case(".equ lode 1\nLOAD [laod]" => matches Error::UndefinedSymbol {
+        suggestion: Some(ref s),
+        ..
+    } if s == "lode"; "no opcode suggested for operand")]
+    #[test_case("banana" => matches Error::UndefinedSymbol { suggestion: None, .. }; "nothing close")]
+    #[test_case(".define a 1" => matches Error::UnknownDirective { .. }; "unknown directive")]
+    #[test_case(".equ limit\nLOAD [0x10]" => Error::MissingArgument {
+        directive: 0..10,
+        usage: ".equ name value",
+    }; "missing value")]
+    #[test_case(".word" => Error::MissingArgument {
+        directive: 0..5,
+        usage: ".word name [value]",
+    }; "missing name")]
+    #[test_case("lod [0x10]" => matches Error::UnknownInstruction { .. }; "misspelt instruction")]
+    #[test_case(".equ big 0x7f\nLOAD [big+1]" => matches Error::AddressOutOfRange { .. }; "offset out of range")]
+    #[test_case(".array big 0x81" => matches Error::OutOfMemory { cells: 0x81, .. }; "larger than memory")]
+    #[test_case(".array a 0x7f\nLOAD [0x10]" => matches Error::OutOfMemory { .. }; "no free run")]
+    fn symbol_error(input: &str) -> Error {
+        Program::parse_source(input).expect_err("no error thrown")
+    }
+
+    #[test_case("LOAD [0x80]" => matches Error::AddressOutOfRange {
+        address: std::ops::Range { start: 5, end: 11 },
+        max: 0x7f,
+    })]
+    #[test_case("10 0x8000" => matches Error::LiteralOutOfRange {
+        literal: std::ops::Range { start: 3, end: 9 },
+        max: 0x7fff,
+    })]
+    #[test_case("LOAD [0x29ab18]" => matches Error::AddressOutOfRange {
+        address: std::ops::Range { start: 5, end: 15 },
+        max: 0x7f,
+    }; "address too large for any target")]
+    #[test_case("0x29ab18" => matches Error::LiteralOutOfRange {
+        literal: std::ops::Range { start: 0, end: 8 },
+        max: 0x7fff,
+    }; "literal too large for any target")]
+    #[test_case(".equ a 1\nLOAD [a+0x10000]" => matches Error::AddressOutOfRange {
+        address: std::ops::Range { start: 14, end: 25 },
+        ..
+    }; "offset too large for any target")]
+    #[test_case(".equ table [0x29ab18]" => matches Error::AddressOutOfRange { .. }; "symbol too large for any target")]
+    #[test_case("JUMP [0x29ab18]" => matches Error::BadOperand { .. }; "jump to large address")]
+    fn out_of_range(input: &str) -> Error {
+        Program::parse_source(input).expect_err("no error thrown")
+    }
+
+    #[test_case("10 HALT", InstructionSet::Base => matches Err(Error::UnsupportedInstruction {
+        instruction: std::ops::Range { start: 3, end: 7 },
+    }); "halt")]
+    #[test_case("a: JUMPZ :a", InstructionSet::Base => matches Err(Error::UnsupportedInstruction { .. }); "conditional jump")]
+    #[test_case("a: JUMPZ :a JUMPN :a HALT", InstructionSet::Extended => Ok(4); "extended")]
+    #[test_case(".equ halt 0x10\nLOAD [halt]\nhalt", InstructionSet::Base => Ok(2); "extended opcodes are names on base")]
+    #[test_case("jumpz :nowhere", InstructionSet::Base => matches Err(Error::UnsupportedInstruction {
+        instruction: std::ops::Range { start: 0, end: 5 },
+    }); "extended opcode with operand on base")]
+    #[test_case(".equ halt 0x10", InstructionSet::Extended => matches Err(Error::MissingArgument { .. }); "extended opcodes are reserved on extended")]
+    fn instruction_set(input: &str, instruction_set: InstructionSet) -> Result<usize, Error> {
+        let target = TargetSpec {
+            instruction_set,
+            ..TargetSpec::default()
+        };
+        Program::parse_source_for(input, &target)
+            .map(|program| program.statements().len())
+            .map_err(|mut errors| errors.remove(0))
+    }
+
+    #[test_case(0 => Some(0))]
+    #[test_case(3 => Some(1); "inside instruction")]
+    #[test_case(9 => Some(1); "inside operand")]
+    #[test_case(17 => Some(2); "label")]
+    #[test_case(2 => None; "whitespace")]
+    #[test_case(25 => None; "comment")]
+    #[test_case(100 => None; "past the end")]
+    fn statement_at(offset: usize) -> Option<usize> {
+        let program = Program::parse_source(
+            "10 STORE [0x10]
+end: ; done",
+        )
+        .expect("parse error");
+        program.statement_at(offset)
+    }
+
+    #[test_case("10" => Some(10))]
+    #[test_case("0x1f" => Some(31))]
+    #[test_case("0b101" => Some(5))]
+    #[test_case("0x" => None; "prefix only")]
+    #[test_case("1x5" => None; "prefix after a digit")]
+    #[test_case("ten" => None)]
+    #[test_case("" => None; "empty")]
+    fn int(raw: &str) -> Option<usize> {
+        parse_int(raw)
+    }
+
+    #[test]
+    fn display_jump_without_label() {
+        let program = Program {
+            statements: vec![Literal(1), InstrLine(Jump(0)), InstrLine(Jump(3))],
+            spans: Vec::new(),
+            operands: Vec::new(),
+            globals: Vec::new(),
+            externs: Vec::new(),
+            memory: Vec::new(),
+        };
+        assert_eq!(
+            program.to_string(),
+            "L0000:\n    1\n    JUMP :L0000\n    JUMP :L0003\nL0003:\n"
+        );
+    }
+
+    #[test]
+    fn display_synthesised_labels_reassemble() {
+        let program = Program {
+            statements: vec![
+                Label("L0001".to_string()),
+                Literal(1),
+                InstrLine(Jump(1)),
+                InstrLine(Jump(3)),
+                InstrLine(Jump(5)),
+            ],
+            spans: Vec::new(),
+            operands: Vec::new(),
+            globals: Vec::new(),
+            externs: Vec::new(),
+            memory: Vec::new(),
+        };
+        let source = program.to_string();
+        assert_eq!(
+            source,
+            "L0001:\nL0001_:\n    1\n    JUMP :L0001_\nL0003:\n    JUMP :L0003\n    JUMP :L0005\nL0005:\n"
+        );
+
+        let target = TargetSpec::default();
+        let reassembled = Program::parse_source(&source).expect("parse error");
+        assert_eq!(
+            assemble::assemble(&reassembled, &target).unwrap(),
+            assemble::assemble(&program, &target).unwrap()
+        );
+    }
+
+    #[test]
+    fn wider_target() {
+        let target = TargetSpec {
+            address_bits: 8,
+            literal_bits: 16,
+            ..TargetSpec::default()
+        };
+        Program::parse_source_for("0xffff STORE [0xff]", &target).expect("parse error");
     }
 }
@@ -0,0 +1,600 @@
+//! Macro definition and expansion.
+//!
+//! A macro is defined with its parameters on the same line as `.macro`:
+//! ```text
+//! .macro copy from to
+//!     LOAD [from]
+//!     STORE [to]
+//! .endm
+//! ```
+//! and invoked by its name with the arguments on the same line, like `copy [0x10] [total]`.
+//! In the body, a parameter can stand in for a literal (`from`), an address (`[from]` or
+//! `[from+1]`) or a label (`:from`).
+//! Labels defined in the body are renamed in each expansion, like `loop--spin-1` for `loop` in
+//! the first expansion of `spin`, so they don't clash with each other and are still valid source.
+//!
+//! Expansion works on tokens, before statements are parsed.
+//! Expanded tokens get spans past the end of the source, which [`Expansion`] traces back to the
+//! macro body and the invocation.
+
+use std::{
+    collections::{HashMap, HashSet},
+    iter::Peekable,
+};
+
+use super::{
+    error::{Error, Span},
+    labels::is_numeric,
+    lex::{Token, Tokens, Wide},
+    Program,
+};
+
+/// Separator between a label from a macro body and the expansion it was renamed for, like
+/// `loop--spin-1`, which is reserved so the renamed labels can be told apart.
+pub(crate) const EXPANSION_SEPARATOR: &str = "--";
+
+/// Token along with the macro invocations it was expanded from, outermost first.
+type Expanded = (Token, Span, Vec<Span>);
+
+/// First line of a macro definition.
+struct Header {
+    name: String,
+    params: Vec<String>,
+    /// Span from `.macro` to the last parameter.
+    span: Span,
+}
+
+struct Macro {
+    header: Header,
+    body: Vec<(Token, Span)>,
+}
+
+/// Whether there's no line break between two spans.
+pub(crate) fn same_line(source: &str, before: &Span, after: &Span) -> bool {
+    before.end <= after.start && !source[before.end..after.start].contains('\n')
+}
+
+/// Take the name and parameters of a macro, after its `.macro`.
+fn take_header<I: Iterator<Item = (Token, Span)>>(
+    stream: &mut Peekable<I>,
+    directive: Span,
+    source: &str,
+) -> Result<Header, Error> {
+    let take_ident = |stream: &mut Peekable<I>| {
+        stream.next_if(|(tok, span)| {
+            matches!(tok, Token::Ident(_)) && same_line(source, &directive, span)
+        })
+    };
+    let Some((Token::Ident(name), name_span)) = take_ident(stream) else {
+        return Err(Error::MissingArgument {
+            directive,
+            usage: ".macro name [parameters...]",
+        });
+    };
+    let mut end = name_span.end;
+    let mut params = Vec::new();
+    while let Some((Token::Ident(param), span)) = take_ident(stream) {
+        end = span.end;
+        params.push(param);
+    }
+    Ok(Header {
+        name,
+        params,
+        span: directive.start..end,
+    })
+}
+
+/// Whether a token can be an argument to a macro.
+fn is_argument(tok: &Token) -> bool {
+    matches!(
+        tok,
+        Token::Address(_)
+            | Token::Symbol(_)
+            | Token::Literal(_)
+            | Token::JumpLabel(_)
+            | Token::Ident(_)
+    )
+}
+
+/// Take the arguments of an invocation, returning them with the span of the whole invocation.
+fn take_args<I: Iterator<Item = Expanded>>(
+    stream: &mut Peekable<I>,
+    name: &Span,
+    source: &str,
+) -> (Vec<Expanded>, Span) {
+    let mut args: Vec<Expanded> = Vec::new();
+    loop {
+        let last = args.last().map_or(name, |(_, span, _)| span);
+        match stream.next_if(|(tok, span, _)| is_argument(tok) && same_line(source, last, span)) {
+            Some(arg) => args.push(arg),
+            None => break,
+        }
+    }
+    let end = args.last().map_or(name.end, |(_, span, _)| span.end);
+    (args, name.start..end)
+}
+
+/// Substitute an argument for a parameter used as an address, like `[param+1]`.
+fn substitute_address(arg: &Token, offset: Wide) -> Token {
+    match arg {
+        Token::Address(a) | Token::Literal(a) => Token::Address(a.saturating_add(offset)),
+        Token::Symbol((name, o)) => Token::Symbol((name.clone(), o.saturating_add(offset))),
+        Token::Ident(name) => Token::Symbol((name.clone(), offset)),
+        // reported as a bad operand by the parser
+        other => other.clone(),
+    }
+}
+
+/// Render an argument token as it's written.
+fn render_argument(tok: &Token) -> String {
+    match tok {
+        Token::Address(a) => format!("[0x{a:x}]"),
+        Token::Symbol((name, 0)) => format!("[{name}]"),
+        Token::Symbol((name, offset)) => format!("[{name}+{offset}]"),
+        Token::Literal(l) => l.to_string(),
+        Token::JumpLabel(label) => format!(":{label}"),
+        Token::Ident(name) => name.clone(),
+        _ => String::new(),
+    }
+}
+
+/// Wrap an error in the invocations it happened in, outermost first.
+fn in_macros(error: Error, invocations: &[Span]) -> Error {
+    invocations
+        .iter()
+        .rev()
+        .fold(error, |error, invocation| Error::InMacro {
+            invocation: invocation.clone(),
+            error: Box::new(error),
+        })
+}
+
+/// Take the macro definitions out of a token stream.
+fn collect(
+    tokens: Vec<(Token, Span)>,
+    source: &str,
+    errors: &mut Vec<Error>,
+) -> (HashMap<String, Macro>, Vec<(Token, Span)>) {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut rest = Vec::new();
+    let mut stream = tokens.into_iter().peekable();
+    while let Some((tok, span)) = stream.next() {
+        match tok {
+            Token::Directive(ref directive) if directive == "macro" => {
+                let header = match take_header(&mut stream, span, source) {
+                    Ok(header) => header,
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                };
+                let mut body = Vec::new();
+                let mut ended = false;
+                for (tok, span) in stream.by_ref() {
+                    if matches!(tok, Token::Directive(ref d) if d == "endm") {
+                        ended = true;
+                        break;
+                    }
+                    body.push((tok, span));
+                }
+                if !ended {
+                    errors.push(Error::UnterminatedMacro {
+                        definition: header.span,
+                    });
+                    continue;
+                }
+                if let Some(prev) = macros.get(&header.name) {
+                    errors.push(Error::DuplicateMacro {
+                        prev: prev.header.span.clone(),
+                        this: header.span,
+                    });
+                    continue;
+                }
+                macros.insert(header.name.clone(), Macro { header, body });
+            }
+            Token::Directive(ref directive) if directive == "endm" => {
+                errors.push(Error::UnexpectedToken { token: span });
+            }
+            _ => rest.push((tok, span)),
+        }
+    }
+    (macros, rest)
+}
+
+struct Expander<'a> {
+    source: &'a str,
+    macros: HashMap<String, Macro>,
+    /// Number of expansions so far, to make label names unique.
+    count: usize,
+    /// Every label in the source and every renamed label so far, so renaming never clashes.
+    labels: HashSet<String>,
+    /// Macros being expanded, outermost first, so a macro which invokes itself is caught before
+    /// it expands forever.
+    active: Vec<String>,
+    errors: Vec<Error>,
+}
+
+impl Expander<'_> {
+    /// Expand every invocation in `tokens` into `out`, recursively.
+    fn expand(&mut self, tokens: Vec<Expanded>, out: &mut Vec<Expanded>) {
+        let mut stream = tokens.into_iter().peekable();
+        while let Some((tok, span, invocations)) = stream.next() {
+            let Some(name) = (match &tok {
+                Token::Ident(name) if self.macros.contains_key(name) => Some(name.clone()),
+                _ => None,
+            }) else {
+                out.push((tok, span, invocations));
+                continue;
+            };
+            let (args, invocation) = take_args(&mut stream, &span, self.source);
+
+            let expected = self.macros[&name].header.params.len();
+            if args.len() != expected {
+                self.errors.push(in_macros(
+                    Error::WrongArgumentCount {
+                        invocation,
+                        expected,
+                        found: args.len(),
+                    },
+                    &invocations,
+                ));
+                continue;
+            }
+            if self.active.contains(&name) {
+                self.errors.push(in_macros(
+                    Error::MacroRecursion { invocation },
+                    &invocations,
+                ));
+                continue;
+            }
+
+            let mut invocations = invocations;
+            invocations.push(invocation);
+            let body = self.instantiate(&name, &args, &invocations);
+            self.active.push(name);
+            self.expand(body, out);
+            self.active.pop();
+        }
+    }
+
+    /// Copy the body of a macro, with arguments substituted and labels renamed.
+    fn instantiate(
+        &mut self,
+        name: &str,
+        args: &[Expanded],
+        invocations: &[Span],
+    ) -> Vec<Expanded> {
+        self.count += 1;
+        let mac = &self.macros[name];
+        let mut renamed = HashMap::new();
+        for (tok, _) in &mac.body {
+            // numeric labels are found by position, so already can't clash
+            let Token::Label(label) = tok else { continue };
+            if is_numeric(label) || renamed.contains_key(label.as_str()) {
+                continue;
+            }
+            let mut new = format!("{label}{EXPANSION_SEPARATOR}{name}-{}", self.count);
+            while self.labels.contains(&new) {
+                new.push('_');
+            }
+            self.labels.insert(new.clone());
+            renamed.insert(label.as_str(), new);
+        }
+        let arg = |param: &str| {
+            let i = mac.header.params.iter().position(|p| p == param)?;
+            Some(&args[i].0)
+        };
+
+        mac.body
+            .iter()
+            .map(|(tok, span)| {
+                let substituted = match tok {
+                    Token::Ident(param) => arg(param).cloned(),
+                    Token::Symbol((param, offset)) => {
+                        arg(param).map(|arg| substitute_address(arg, *offset))
+                    }
+                    Token::JumpLabel(label) => match arg(label) {
+                        Some(Token::JumpLabel(target) | Token::Ident(target)) => {
+                            Some(Token::JumpLabel(target.clone()))
+                        }
+                        Some(other) => Some(other.clone()),
+                        None => renamed.get(label.as_str()).cloned().map(Token::JumpLabel),
+                    },
+                    Token::Label(label) => renamed.get(label.as_str()).cloned().map(Token::Label),
+                    _ => None,
+                };
+                (
+                    substituted.unwrap_or_else(|| tok.clone()),
+                    span.clone(),
+                    invocations.to_vec(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Where expanded tokens came from.
+pub(crate) struct Expansion {
+    /// Start of the spans given to expanded tokens, just past the end of the source.
+    base: usize,
+    /// Real span and invocations of each expanded token.
+    origins: Vec<(Span, Vec<Span>)>,
+    /// Errors in macro definitions and invocations.
+    pub(crate) errors: Vec<Error>,
+}
+
+impl Expansion {
+    fn origin(&self, offset: usize) -> &(Span, Vec<Span>) {
+        &self.origins[(offset - self.base) / 2]
+    }
+
+    /// Translate a span of the expanded tokens to a span of source code.
+    fn real_span(&self, span: Span) -> Span {
+        if span.end <= self.base {
+            return span;
+        }
+        let last = &self.origin(span.end - 1).0;
+        if span.start < self.base {
+            return span.start..last.end.max(span.start);
+        }
+        let first = &self.origin(span.start).0;
+        if first.start <= last.end {
+            first.start..last.end
+        } else {
+            first.clone()
+        }
+    }
+
+    /// Whether there's no line break between two expanded tokens where they were written.
+    pub(crate) fn same_line(&self, source: &str, before: &Span, after: &Span) -> bool {
+        same_line(
+            source,
+            &self.real_span(before.clone()),
+            &self.real_span(after.clone()),
+        )
+    }
+
+    /// Point an error in the expanded tokens at the source, wrapping it in the invocations which
+    /// produced it.
+    pub(crate) fn locate(&self, error: Error) -> Error {
+        let Some(expanded) = error
+            .spans()
+            .into_iter()
+            .find(|span| span.start >= self.base)
+        else {
+            return error;
+        };
+        let invocations = self.origin(expanded.start).1.clone();
+        in_macros(
+            error.map_spans(&mut |span| self.real_span(span)),
+            &invocations,
+        )
+    }
+
+    /// Point the spans of a program parsed from the expanded tokens at the source.
+    ///
+    /// Statements from an expansion get the span of the outermost invocation.
+    pub(crate) fn relocate(&self, mut program: Program) -> Program {
+        let relocate = |span: Span| {
+            if span.start >= self.base {
+                self.origin(span.start).1[0].clone()
+            } else {
+                span
+            }
+        };
+        program.spans = program.spans.into_iter().map(relocate).collect();
+        program.operands = program
+            .operands
+            .into_iter()
+            .map(|span| span.map(relocate))
+            .collect();
+        program
+    }
+}
+
+/// Expand every macro in a token stream.
+///
+/// Definitions are removed from the stream, and can come after their invocations.
+pub(crate) fn expand(tokens: Vec<(Token, Span)>, source: &str) -> (Vec<(Token, Span)>, Expansion) {
+    let mut errors = Vec::new();
+    let (macros, rest) = collect(tokens, source, &mut errors);
+    let labels = rest
+        .iter()
+        .map(|(tok, _)| tok)
+        .chain(
+            macros
+                .values()
+                .flat_map(|mac| mac.body.iter().map(|(tok, _)| tok)),
+        )
+        .filter_map(|tok| match tok {
+            Token::Label(label) => Some(label.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut expander = Expander {
+        source,
+        macros,
+        count: 0,
+        labels,
+        active: Vec::new(),
+        errors,
+    };
+    let mut out = Vec::new();
+    expander.expand(
+        rest.into_iter()
+            .map(|(tok, span)| (tok, span, Vec::new()))
+            .collect(),
+        &mut out,
+    );
+
+    let mut expansion = Expansion {
+        base: source.len() + 1,
+        origins: Vec::new(),
+        errors: expander.errors,
+    };
+    let tokens = out
+        .into_iter()
+        .map(|(tok, span, invocations)| {
+            if invocations.is_empty() {
+                return (tok, span);
+            }
+            // one offset per token, with a gap so neighbouring tokens don't touch
+            let start = expansion.base + expansion.origins.len() * 2;
+            expansion.origins.push((span, invocations));
+            (tok, start..start + 1)
+        })
+        .collect();
+    (tokens, expansion)
+}
+
+/// Separate macro syntax from the statements in a token stream, without expanding anything.
+///
+/// Returns the remaining tokens, including the bodies of macros, and the canonical text of each
+/// macro header, `.endm` and invocation.
+pub(crate) fn split_syntax(
+    tokens: Vec<(Token, Span)>,
+    source: &str,
+) -> Result<(Tokens, Vec<(String, Span)>), Error> {
+    let mut syntax = Vec::new();
+    let mut names = HashSet::new();
+    let mut rest = Vec::new();
+    let mut stream = tokens.into_iter().peekable();
+    while let Some((tok, span)) = stream.next() {
+        match tok {
+            Token::Directive(ref directive) if directive == "macro" => {
+                let header = take_header(&mut stream, span, source)?;
+                let mut text = format!(".macro {}", header.name);
+                for param in &header.params {
+                    text.push(' ');
+                    text.push_str(param);
+                }
+                names.insert(header.name);
+                syntax.push((text, header.span));
+            }
+            Token::Directive(ref directive) if directive == "endm" => {
+                syntax.push((".endm".to_owned(), span));
+            }
+            _ => rest.push((tok, span, Vec::new())),
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut stream = rest.into_iter().peekable();
+    while let Some((tok, span, _)) = stream.next() {
+        match tok {
+            Token::Ident(ref name) if names.contains(name) => {
+                let (args, invocation) = take_args(&mut stream, &span, source);
+                let mut text = name.clone();
+                for (arg, _, _) in &args {
+                    text.push(' ');
+                    text.push_str(&render_argument(arg));
+                }
+                syntax.push((text, invocation));
+            }
+            _ => tokens.push((tok, span)),
+        }
+    }
+    Ok((tokens, syntax))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Instruction::*, Statement, Statement::*};
+    use test_case::test_case;
+
+    #[test_case(".macro copy from to\nLOAD [from]\nSTORE [to+1]\n.endm\ncopy [0x10] [0x20]" => vec![
+        InstrLine(Load(0x10)),
+        InstrLine(Store(0x21)),
+    ]; "addresses")]
+    #[test_case(".macro put value\nvalue\n.endm\nput 5" => vec![Literal(5)]; "literal")]
+    #[test_case(".macro spin\nloop: JUMP :loop\n.endm\nspin\nspin" => vec![
+        Label("loop--spin-1".to_owned()),
+        InstrLine(Jump(0)),
+        Label("loop--spin-2".to_owned()),
+        InstrLine(Jump(2)),
+    ]; "hygienic labels")]
+    #[test_case(".macro spin\n1: JUMP :1b\n.endm\nmain: spin\n.loop: JUMP :.loop" => vec![
+        Label("main".to_owned()),
+        Label("1".to_owned()),
+        InstrLine(Jump(1)),
+        Label(".loop".to_owned()),
+        InstrLine(Jump(3)),
+    ]; "local labels")]
+    #[test_case("end: goto :end\n.macro goto where\nJUMP :where\n.endm" => vec![
+        Label("end".to_owned()),
+        InstrLine(Jump(0)),
+    ]; "label argument")]
+    #[test_case(".macro inc cell\nLOAD [cell] ADD [one] STORE [cell]\n.endm\n.macro twice cell\ninc [cell]\ninc [cell]\n.endm\n.equ one 0x7f\ntwice [0x10]" => vec![
+        InstrLine(Load(0x10)),
+        InstrLine(Add(0x7f)),
+        InstrLine(Store(0x10)),
+        InstrLine(Load(0x10)),
+        InstrLine(Add(0x7f)),
+        InstrLine(Store(0x10)),
+    ]; "nested")]
+    fn expansion(input: &str) -> Vec<Statement<usize>> {
+        Program::parse_source(input)
+            .expect("parse error")
+            .statements()
+            .to_vec()
+    }
+
+    #[test_case(".macro bad x\nLOAD x\n.endm\nbad 5" => Error::InMacro {
+        invocation: 26..31,
+        error: Box::new(Error::BadOperand {
+            opcode: 13..17,
+            operand: 18..19,
+            wanted: crate::error::OperandType::Address,
+        }),
+    }; "error in body")]
+    #[test_case(".macro two a b\n.endm\ntwo 1" => Error::WrongArgumentCount {
+        invocation: 21..26,
+        expected: 2,
+        found: 1,
+    }; "argument count")]
+    #[test_case(".macro loop\nloop\n.endm\nloop" => Error::InMacro {
+        invocation: 23..27,
+        error: Box::new(Error::MacroRecursion { invocation: 12..16 }),
+    }; "recursion")]
+    #[test_case(".macro a\na\na\n.endm\na" => Error::InMacro {
+        invocation: 19..20,
+        error: Box::new(Error::MacroRecursion { invocation: 9..10 }),
+    }; "recursion twice")]
+    #[test_case(".macro a\nb\n.endm\n.macro b\na\n.endm\na" => Error::InMacro {
+        invocation: 34..35,
+        error: Box::new(Error::InMacro {
+            invocation: 9..10,
+            error: Box::new(Error::MacroRecursion { invocation: 26..27 }),
+        }),
+    }; "indirect recursion")]
+    #[test_case(".macro open\n10" => Error::UnterminatedMacro { definition: 0..11 }; "unterminated")]
+    #[test_case(".macro\n10\n.endm" => Error::MissingArgument {
+        directive: 0..6,
+        usage: ".macro name [parameters...]",
+    }; "missing name")]
+    fn expansion_error(input: &str) -> Error {
+        Program::parse_source(input).expect_err("no error thrown")
+    }
+
+    #[test]
+    fn renamed_labels_reassemble() {
+        let program =
+            Program::parse_source(".macro spin\nloop: JUMP :loop\n.endm\nspin\nloop--spin-1: spin")
+                .expect("parse error");
+        assert_eq!(
+            program.statements()[0],
+            Label("loop--spin-1_".to_owned()),
+            "renamed label clashes"
+        );
+        let reparsed = Program::parse_source(&program.to_string()).expect("not valid source");
+        assert_eq!(reparsed.statements(), program.statements());
+    }
+
+    #[test]
+    fn spans_point_at_invocation() {
+        let program = Program::parse_source(".macro two\n1 2\n.endm\n10 two").expect("parse error");
+        assert_eq!(program.span_of(1), Some(24..27));
+        assert_eq!(program.statement_at(25), Some(1));
+    }
+}
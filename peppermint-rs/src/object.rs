@@ -0,0 +1,490 @@
+//! Relocatable objects and linking them into one image.
+//!
+//! A file parsed with [`Program::parse_object`] can export labels with `.global name`, and jump
+//! to labels exported by other objects once it declares them with `.extern name`.
+//! Where an object ends up in the image isn't known until it's linked, so an [`Object`] keeps a
+//! [`Relocation`] for every jump, which [`link`] patches once every object has been placed.
+//!
+//! Objects are placed in the order they're given to [`link`], and execution starts at the first.
+//! Running off the end of an object carries straight on into the next one, as if their files had
+//! been concatenated.
+//! Objects can't reserve memory with `.word` or `.array`, since each object would allocate its
+//! cells without knowing which cells the others had taken.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use super::{
+    assemble::{self, opcode, word_indices, INSTRUCTION_FLAG, OPCODE_SHIFT, OPERAND_MASK},
+    debug_info::Symbol,
+    error::{self, Span},
+    labels::LabelTable,
+    target::TargetSpec,
+    Directive, DoubleWord, Instruction, Program, SpannedStatement, StatNum, Statement,
+};
+
+/// Labels exported and imported by a file, collected while parsing it.
+#[derive(Default)]
+pub(crate) struct Linkage {
+    /// Name and directive span of each export.
+    exports: Vec<(String, Span)>,
+    /// Name and directive span of each import.
+    imports: Vec<(String, Span)>,
+}
+
+impl Linkage {
+    /// Record a `.global` or `.extern` directive.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`error::Error::ExternOutsideObject`] for an import if the file isn't being
+    /// parsed as an object.
+    pub(crate) fn declare(
+        &mut self,
+        directive: Directive,
+        span: Span,
+        object: bool,
+    ) -> Result<(), error::Error> {
+        match directive {
+            Directive::Global { name } => self.exports.push((name, span)),
+            Directive::Extern { name } => {
+                // keep the import anyway, so jumps to it aren't reported as well
+                self.imports.push((name, span.clone()));
+                if !object {
+                    return Err(error::Error::ExternOutsideObject { directive: span });
+                }
+            }
+            _ => unreachable!("only linkage directives are declared"),
+        }
+        Ok(())
+    }
+
+    /// Add a label statement for each import after `statements`, where jumps to it land until the
+    /// program is linked.
+    ///
+    /// Returns the statement number of each import.
+    pub(crate) fn import(
+        &self,
+        statements: &mut Vec<SpannedStatement>,
+        labels: &mut LabelTable,
+        errors: &mut Vec<error::Error>,
+    ) -> Vec<StatNum> {
+        let mut imported = Vec::new();
+        for (name, span) in &self.imports {
+            if let Err(e) = labels.define(name, statements.len(), span.clone()) {
+                errors.push(e);
+                continue;
+            }
+            imported.push(statements.len());
+            statements.push(SpannedStatement {
+                statement: Statement::Label(name.clone()),
+                span: span.clone(),
+                operand: None,
+                symbol: None,
+            });
+        }
+        imported
+    }
+
+    /// Find the label statement of each export.
+    ///
+    /// Labels exported more than once are only returned once.
+    pub(crate) fn export(
+        &self,
+        labels: &LabelTable,
+        errors: &mut Vec<error::Error>,
+    ) -> Vec<StatNum> {
+        let mut exported = Vec::new();
+        for (name, span) in &self.exports {
+            // an import isn't defined here, so can't be exported from here
+            if self.imports.iter().any(|(import, _)| import == name) {
+                errors.push(error::Error::UndefinedLabel {
+                    reference: span.clone(),
+                    suggestion: None,
+                    scope: error::LabelScope::Global,
+                });
+                continue;
+            }
+            match labels.lookup(name, span.clone()) {
+                Ok(stat) if !exported.contains(&stat) => exported.push(stat),
+                Ok(_) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+        exported
+    }
+}
+
+/// Error in linking objects together.
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum Error {
+    /// Label imported by an object which no object exports.
+    #[error("`{name}` is imported by {object}, but no object exports it")]
+    UndefinedGlobal {
+        /// Name of the label.
+        name: String,
+        /// Name of the importing object.
+        object: String,
+    },
+    /// Label exported by more than one object.
+    #[error("`{name}` is exported by both {first} and {second}")]
+    DuplicateGlobal {
+        /// Name of the label.
+        name: String,
+        /// Name of the first object exporting it.
+        first: String,
+        /// Name of the object exporting it again.
+        second: String,
+    },
+    /// Relocation which doesn't point at a jump in its object.
+    #[error("relocation of word {word} in {object} doesn't point at a jump")]
+    BadRelocation {
+        /// Name of the object.
+        object: String,
+        /// Index of the word in the object.
+        word: usize,
+    },
+    /// Jump target is beyond the reach of the operand field once objects are placed.
+    #[error("jump in word {word} of {object} is too far into the image to encode")]
+    JumpOutOfRange {
+        /// Name of the object.
+        object: String,
+        /// Index of the jump in the object.
+        word: usize,
+    },
+}
+
+/// Jump whose target is only known once objects are placed in an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Relocation {
+    /// Jump to a label in the same object, whose operand is a word index into the object.
+    Local {
+        /// Index of the jump in the object.
+        word: usize,
+    },
+    /// Jump to a label exported by another object.
+    Extern {
+        /// Index of the jump in the object.
+        word: usize,
+        /// Name of the imported label.
+        name: String,
+    },
+}
+
+impl Relocation {
+    /// Index of the jump in its object.
+    #[must_use]
+    pub fn word(&self) -> usize {
+        match self {
+            Self::Local { word } | Self::Extern { word, .. } => *word,
+        }
+    }
+}
+
+/// Machine code of one file which can be placed anywhere in an image.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Object {
+    /// Name of the file the object was assembled from, which linking errors refer to.
+    pub name: String,
+    /// Machine code, as if the object were the whole image.
+    ///
+    /// Jumps to imported labels target the word just past the end of the object.
+    pub words: Vec<DoubleWord>,
+    /// Every exported label and the word it points at, in word order.
+    pub globals: Vec<Symbol>,
+    /// Every jump in the object, in word order.
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    /// Assemble a program parsed with [`Program::parse_object`] into an object for `target`.
+    ///
+    /// `name` is only recorded for reporting errors.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`assemble::Error`] if any operand of the program doesn't fit the encoding or the
+    /// target, or if an import or export isn't a label.
+    pub fn assemble(
+        program: &Program,
+        name: impl Into<String>,
+        target: &TargetSpec,
+    ) -> Result<Self, assemble::Error> {
+        let words = assemble::assemble(program, target)?;
+        let indices = word_indices(program);
+        let label_name = |stat: StatNum| match program.statements.get(stat) {
+            Some(Statement::Label(name)) => Ok(name.clone()),
+            _ => Err(assemble::Error::LinkTargetNotLabel { statement: stat }),
+        };
+
+        let relocations = program
+            .statements
+            .iter()
+            .enumerate()
+            .filter_map(|(stat, statement)| match statement {
+                Statement::InstrLine(
+                    Instruction::Jump(target)
+                    | Instruction::JumpZ(target)
+                    | Instruction::JumpN(target),
+                ) => {
+                    let word = indices[stat];
+                    Some(if program.externs.contains(target) {
+                        label_name(*target).map(|name| Relocation::Extern { word, name })
+                    } else {
+                        Ok(Relocation::Local { word })
+                    })
+                }
+                _ => None,
+            })
+            .collect::<Result<_, _>>()?;
+        let mut globals = program
+            .globals
+            .iter()
+            .map(|&stat| {
+                Ok(Symbol {
+                    name: label_name(stat)?,
+                    word: indices[stat],
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        globals.sort_by_key(|symbol| symbol.word);
+
+        Ok(Self {
+            name: name.into(),
+            words,
+            globals,
+            relocations,
+        })
+    }
+}
+
+/// Link objects into one image, placing them one after another in the order given.
+///
+/// # Errors
+///
+/// Returns every [`enum@Error`] found, such as imports which no object exports.
+pub fn link(objects: &[Object]) -> Result<Vec<DoubleWord>, Vec<Error>> {
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut len = 0;
+    for object in objects {
+        bases.push(len);
+        len += object.words.len();
+    }
+
+    let mut errors = Vec::new();
+    // absolute word and exporting object of every global
+    let mut globals: HashMap<&str, (usize, &str)> = HashMap::new();
+    for (object, base) in objects.iter().zip(&bases) {
+        for symbol in &object.globals {
+            match globals.entry(&symbol.name) {
+                Entry::Vacant(entry) => {
+                    entry.insert((base + symbol.word, &object.name));
+                }
+                Entry::Occupied(entry) => errors.push(Error::DuplicateGlobal {
+                    name: symbol.name.clone(),
+                    first: entry.get().1.to_owned(),
+                    second: object.name.clone(),
+                }),
+            }
+        }
+    }
+
+    let jumps = [
+        Instruction::Jump(()),
+        Instruction::JumpZ(()),
+        Instruction::JumpN(()),
+    ]
+    .map(|jump| INSTRUCTION_FLAG | (opcode(&jump) << OPCODE_SHIFT));
+    let mut image = Vec::with_capacity(len);
+    for (object, base) in objects.iter().zip(&bases) {
+        image.extend_from_slice(&object.words);
+        for relocation in &object.relocations {
+            let word = relocation.word();
+            let Some(instruction) = object
+                .words
+                .get(word)
+                .filter(|w| jumps.contains(&(*w & !OPERAND_MASK)))
+            else {
+                errors.push(Error::BadRelocation {
+                    object: object.name.clone(),
+                    word,
+                });
+                continue;
+            };
+            let target = match relocation {
+                Relocation::Local { .. } => base + usize::from(instruction & OPERAND_MASK),
+                Relocation::Extern { name, .. } => {
+                    if let Some((target, _)) = globals.get(name.as_str()) {
+                        *target
+                    } else {
+                        let error = Error::UndefinedGlobal {
+                            name: name.clone(),
+                            object: object.name.clone(),
+                        };
+                        // only report each missing label once per object
+                        if !errors.contains(&error) {
+                            errors.push(error);
+                        }
+                        continue;
+                    }
+                }
+            };
+            match DoubleWord::try_from(target)
+                .ok()
+                .filter(|t| *t <= OPERAND_MASK)
+            {
+                Some(target) => image[base + word] = (instruction & !OPERAND_MASK) | target,
+                None => errors.push(Error::JumpOutOfRange {
+                    object: object.name.clone(),
+                    word,
+                }),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(image)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error as ParseError;
+    use test_case::test_case;
+
+    fn object(name: &str, input: &str) -> Object {
+        let target = TargetSpec {
+            instruction_set: crate::target::InstructionSet::Extended,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_object(name, input, &[], &target)
+            .1
+            .expect("parse error");
+        Object::assemble(&program, name, &target).expect("assembly error")
+    }
+
+    #[test]
+    fn assemble_object() {
+        let object = object(
+            "a.ppr",
+            ".global start .extern double\nstart: 10 JUMP :double\nagain: JUMP :again",
+        );
+        assert_eq!(object.words, vec![10, 0xB803, 0xB802]);
+        assert_eq!(
+            object.globals,
+            vec![Symbol {
+                name: "start".to_owned(),
+                word: 0
+            }]
+        );
+        assert_eq!(
+            object.relocations,
+            vec![
+                Relocation::Extern {
+                    word: 1,
+                    name: "double".to_owned()
+                },
+                Relocation::Local { word: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn link_target_not_label() {
+        let program = Program {
+            statements: vec![
+                Statement::Literal(10),
+                Statement::InstrLine(Instruction::Jump(0)),
+            ],
+            spans: Vec::new(),
+            operands: Vec::new(),
+            globals: vec![0],
+            externs: Vec::new(),
+            memory: Vec::new(),
+        };
+        assert_eq!(
+            Object::assemble(&program, "a.ppr", &TargetSpec::default()),
+            Err(assemble::Error::LinkTargetNotLabel { statement: 0 })
+        );
+    }
+
+    #[test_case(".extern f JUMP :f" => matches ParseError::ExternOutsideObject { .. }; "extern outside object")]
+    #[test_case(".global nowhere 10" => matches ParseError::UndefinedLabel { .. }; "undefined export")]
+    fn program_error(input: &str) -> ParseError {
+        Program::parse_source(input).expect_err("no error thrown")
+    }
+
+    #[test_case(".word x 5" => matches ParseError::ReservationInObject { .. }; "reservation")]
+    #[test_case("f: .extern f JUMP :f" => matches ParseError::DuplicateLabel { .. }; "import defined here")]
+    #[test_case(".extern f .global f" => matches ParseError::UndefinedLabel { .. }; "export import")]
+    fn object_error(input: &str) -> ParseError {
+        Program::parse_object("a.ppr", input, &[], &TargetSpec::default())
+            .1
+            .expect_err("no error thrown")
+            .remove(0)
+    }
+
+    #[test_case(".word x 5\nLOAD [x]\nSTORE [x]"; "word")]
+    #[test_case(".array buf 2\nLOAD [buf+1]"; "array")]
+    fn reservation_error_only(input: &str) {
+        let errors = Program::parse_object("a.ppr", input, &[], &TargetSpec::default())
+            .1
+            .expect_err("no error thrown");
+        assert!(
+            matches!(errors[..], [ParseError::ReservationInObject { .. }]),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn link_objects() {
+        let main = object("main.ppr", ".extern double\n10 JUMP :double\nend:");
+        let lib = object(
+            "lib.ppr",
+            ".global double .global end\ndouble: ADD [0x10] JUMP :end\nend: STORE [0x10]",
+        );
+        // the local jump to `end` is relocated past `main`, which has its own `end`
+        assert_eq!(
+            link(&[main, lib]),
+            Ok(vec![10, 0xB802, 0xA010, 0xB804, 0xB010])
+        );
+    }
+
+    #[test]
+    fn link_conditional_jumps() {
+        let main = object(
+            "main.ppr",
+            ".extern f
+JUMPZ :f
+JUMPN :f",
+        );
+        let lib = object(
+            "lib.ppr",
+            ".global f
+f: JUMPN :f HALT",
+        );
+        // the opcode of each jump is kept when its target is patched
+        assert_eq!(link(&[main, lib]), Ok(vec![0xC002, 0xC802, 0xC802, 0xD000]));
+    }
+
+    #[test_case(&[("a.ppr", ".extern f JUMP :f JUMP :f")] => vec![Error::UndefinedGlobal {
+        name: "f".to_owned(),
+        object: "a.ppr".to_owned(),
+    }]; "undefined")]
+    #[test_case(&[("a.ppr", ".global f f: 10"), ("b.ppr", ".global f f: 20")] => vec![Error::DuplicateGlobal {
+        name: "f".to_owned(),
+        first: "a.ppr".to_owned(),
+        second: "b.ppr".to_owned(),
+    }]; "duplicate")]
+    fn link_error(files: &[(&str, &str)]) -> Vec<Error> {
+        let objects: Vec<_> = files
+            .iter()
+            .map(|(name, input)| object(name, input))
+            .collect();
+        link(&objects).expect_err("no error thrown")
+    }
+}
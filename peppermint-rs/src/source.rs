@@ -0,0 +1,119 @@
+//! Programs split across several files.
+//!
+//! Every file of a program is joined into one text, so a span from any of them is an ordinary
+//! [`Span`] and the file it belongs to is found from its offset.
+//! Files are separated by a line break, so tokens from different files never touch.
+//!
+//! A [`SourceMap`] is built by [`crate::Program::parse_with_includes`], and is needed to render
+//! errors and to find the source of statements afterwards.
+
+use super::error::Span;
+
+/// Index of a file in a [`SourceMap`], in the order files were loaded.
+pub type FileId = usize;
+
+/// One file in a [`SourceMap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// Name of the file, as it was found.
+    pub name: String,
+    /// Span of the file's text in the joined text.
+    pub span: Span,
+}
+
+/// Text of every file in a program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    text: String,
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// Make a map of a single file.
+    #[must_use]
+    pub fn new(name: impl Into<String>, text: &str) -> Self {
+        let mut map = Self::default();
+        map.add(name.into(), text);
+        map
+    }
+
+    /// Append a file, returning its id.
+    pub(crate) fn add(&mut self, name: String, text: &str) -> FileId {
+        if !self.files.is_empty() {
+            self.text.push('\n');
+        }
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.files.push(SourceFile {
+            name,
+            span: start..self.text.len(),
+        });
+        self.files.len() - 1
+    }
+
+    /// Text of every file joined together, which spans index into.
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Every file, in the order they were loaded.
+    #[must_use]
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Find the file containing an offset into the joined text.
+    ///
+    /// Offsets between files belong to the file before them.
+    #[must_use]
+    pub fn file_of(&self, offset: usize) -> FileId {
+        self.files
+            .partition_point(|file| file.span.start <= offset)
+            .saturating_sub(1)
+    }
+
+    /// Text of one file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no such file.
+    #[must_use]
+    pub fn source(&self, file: FileId) -> &str {
+        &self.text[self.files[file].span.clone()]
+    }
+
+    /// Name of one file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no such file.
+    #[must_use]
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file].name
+    }
+
+    /// Convert a span of the joined text to the file it's in and a span of that file's text.
+    #[must_use]
+    pub fn local(&self, span: &Span) -> (FileId, Span) {
+        let file = self.file_of(span.start);
+        let start = self.files.get(file).map_or(0, |f| f.span.start);
+        (file, span.start - start..span.end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joined() {
+        let mut map = SourceMap::new("main.ppr", "10\n20");
+        let lib = map.add("lib.ppr".to_owned(), "LOAD [0x10]");
+
+        assert_eq!(map.text(), "10\n20\nLOAD [0x10]");
+        assert_eq!(map.file_of(3), 0);
+        assert_eq!(map.local(&(11..17)), (lib, 5..11));
+        assert_eq!(map.source(lib), "LOAD [0x10]");
+    }
+}
@@ -0,0 +1,203 @@
+//! Symbols defined by directives, and the memory reserved for them.
+//!
+//! `.equ` binds a name to a constant, while `.word` and `.array` reserve cells of RAM whose
+//! addresses are chosen once the whole program has been parsed.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+};
+
+use strum::VariantNames;
+
+use super::{
+    error::{Error, Span},
+    lex::InstructionKind,
+    suggest_name,
+    target::TargetSpec,
+    Address, Directive, DoubleWord, SpannedStatement,
+};
+
+/// Reference to a symbol, standing in for an address or literal until symbols are resolved.
+#[derive(Debug)]
+pub(crate) struct SymbolRef {
+    pub(crate) name: String,
+    /// Constant added to the value of the symbol.
+    pub(crate) offset: Address,
+}
+
+impl fmt::Display for SymbolRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if self.offset != 0 {
+            write!(f, "+{}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cells of RAM reserved by `.word` or `.array`.
+struct Reservation {
+    name: String,
+    len: Address,
+    init: Option<DoubleWord>,
+    /// Span of the directive.
+    span: Span,
+}
+
+/// Every symbol defined in a program.
+#[derive(Default)]
+pub(crate) struct SymbolTable {
+    /// Value and defining directive of each symbol.
+    values: HashMap<String, (DoubleWord, Span)>,
+    /// Memory to allocate, in source order.
+    reservations: Vec<Reservation>,
+}
+
+impl SymbolTable {
+    /// Define the symbol of a directive.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::DuplicateSymbol`] if the name is already taken.
+    pub(crate) fn define(&mut self, directive: Directive, span: Span) -> Result<(), Error> {
+        if let Some((_, prev)) = self.values.get(directive.name()) {
+            return Err(Error::DuplicateSymbol {
+                prev: prev.clone(),
+                this: span,
+            });
+        }
+        let (name, value) = match directive {
+            Directive::Equ { name, value } => (name, value),
+            Directive::Word { name, init } => {
+                self.reserve(name.clone(), 1, init, span.clone());
+                // placeholder until memory is allocated
+                (name, 0)
+            }
+            Directive::Array { name, len } => {
+                self.reserve(name.clone(), len, None, span.clone());
+                (name, 0)
+            }
+            Directive::Global { .. } | Directive::Extern { .. } => {
+                unreachable!("linkage directives don't define symbols")
+            }
+        };
+        self.values.insert(name, (value, span));
+        Ok(())
+    }
+
+    fn reserve(&mut self, name: String, len: Address, init: Option<DoubleWord>, span: Span) {
+        self.reservations.push(Reservation {
+            name,
+            len,
+            init,
+            span,
+        });
+    }
+
+    /// Give each reservation the lowest free run of cells, and return the initial contents of the
+    /// cells which have an initial value.
+    ///
+    /// Cells which `statements` address directly or through `.equ` are never handed out.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::OutOfMemory`] for the first reservation which doesn't fit.
+    pub(crate) fn allocate(
+        &mut self,
+        statements: &[SpannedStatement],
+        target: &TargetSpec,
+    ) -> Result<Vec<(Address, DoubleWord)>, Error> {
+        let reserved: HashSet<&str> = self.reservations.iter().map(|r| r.name.as_str()).collect();
+        let mut taken: BTreeSet<usize> = statements
+            .iter()
+            .filter_map(|stat| {
+                let address = stat.statement.address()?;
+                match &stat.symbol {
+                    None => Some(address),
+                    Some(symbol) if !reserved.contains(symbol.name.as_str()) => {
+                        self.values.get(&symbol.name)?.0.checked_add(symbol.offset)
+                    }
+                    Some(_) => None,
+                }
+            })
+            .map(usize::from)
+            .collect();
+        let cells = (usize::from(target.max_address()) + 1).min(target.memory_size);
+
+        let mut memory = Vec::new();
+        for reservation in &self.reservations {
+            let len = usize::from(reservation.len);
+            let start = cells
+                .checked_sub(len)
+                .and_then(|last| {
+                    (0..=last).find(|&start| taken.range(start..start + len).next().is_none())
+                })
+                .ok_or_else(|| Error::OutOfMemory {
+                    directive: reservation.span.clone(),
+                    cells: reservation.len,
+                })?;
+            taken.extend(start..start + len);
+
+            // the run ends within the address space
+            #[allow(clippy::cast_possible_truncation)]
+            let address = start as Address;
+            self.values
+                .get_mut(&reservation.name)
+                .expect("reservations are symbols")
+                .0 = address;
+            if let Some(init) = reservation.init {
+                memory.push((address, init));
+            }
+        }
+        Ok(memory)
+    }
+
+    /// Find a defined symbol, or an opcode if the name stands alone, which was probably meant
+    /// instead of `name`.
+    fn suggest(&self, name: &str, bare: bool) -> Option<String> {
+        // a name on its own is just as likely to be a misspelt instruction
+        let opcode = bare
+            .then(|| suggest_name(&name.to_ascii_uppercase(), InstructionKind::VARIANTS.iter()))
+            .flatten();
+        opcode.or_else(|| suggest_name(name, self.values.keys()))
+    }
+
+    /// Replace the placeholder in a statement with the value of the symbol it refers to.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::UndefinedSymbol`] if there's no such symbol, or
+    /// [`Error::AddressOutOfRange`] if the offset takes it past the largest address.
+    pub(crate) fn resolve(
+        &self,
+        stat: &mut SpannedStatement,
+        target: &TargetSpec,
+    ) -> Result<(), Error> {
+        let Some(symbol) = &stat.symbol else {
+            return Ok(());
+        };
+        let reference = stat.operand.as_ref().unwrap_or(&stat.span).clone();
+        let Some((value, _)) = self.values.get(&symbol.name) else {
+            // opcodes are only names when the target doesn't have them
+            if stat.operand.is_none() && symbol.name.parse::<InstructionKind>().is_ok() {
+                return Err(Error::UnsupportedInstruction {
+                    instruction: reference,
+                });
+            }
+            return Err(Error::UndefinedSymbol {
+                reference,
+                suggestion: self.suggest(&symbol.name, stat.operand.is_none()),
+            });
+        };
+        // only addresses have an offset
+        let value = value
+            .checked_add(symbol.offset)
+            .ok_or(Error::AddressOutOfRange {
+                address: reference,
+                max: target.max_address(),
+            })?;
+        stat.statement.set_value(value);
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Description of the machine programs are built for.
+//!
+//! Tick Talk's hardware is still being revised, so limits which depend on it are kept in one
+//! place rather than being baked into the parser, assembler and simulator.
+//!
+//! With the `serde` feature enabled a [`TargetSpec`] can be deserialised, e.g. from TOML:
+//! ```toml
+//! address-bits = 8
+//! memory-size = 256
+//! ```
+//! Missing fields take their values from [`TargetSpec::default`].
+
+use super::{Address, Literal};
+
+/// Instruction set supported by the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+#[non_exhaustive]
+pub enum InstructionSet {
+    /// The eight instructions of the original Tick Talk.
+    #[default]
+    Base,
+    /// The base instructions plus the conditional jumps `JUMPZ` and `JUMPN`, and `HALT`.
+    Extended,
+}
+
+/// What happens when arithmetic doesn't fit in the accumulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Overflow {
+    /// Discard the bits which don't fit, like two's complement hardware.
+    #[default]
+    Wrap,
+    /// Clamp the result to zero or the largest value the accumulator holds.
+    Saturate,
+    /// Stop execution with an error.
+    Trap,
+}
+
+/// Machine profile which programs are checked against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields, rename_all = "kebab-case")
+)]
+pub struct TargetSpec {
+    /// Width of RAM addresses in bits.
+    pub address_bits: u32,
+    /// Width of literals in bits.
+    pub literal_bits: u32,
+    /// Width of the accumulator in bits.
+    pub accumulator_bits: u32,
+    /// Number of words of RAM.
+    pub memory_size: usize,
+    /// Instructions the target can execute.
+    pub instruction_set: InstructionSet,
+    /// Behaviour of additions and subtractions which overflow the accumulator.
+    pub overflow: Overflow,
+}
+
+impl Default for TargetSpec {
+    /// The current Tick Talk revision.
+    fn default() -> Self {
+        Self {
+            address_bits: 7,
+            literal_bits: 15,
+            accumulator_bits: 16,
+            memory_size: 1 << 7,
+            instruction_set: InstructionSet::Base,
+            overflow: Overflow::Wrap,
+        }
+    }
+}
+
+/// Largest value which fits in `bits` bits, saturating at the width of `u16`.
+fn max_value(bits: u32) -> u16 {
+    u16::MAX.checked_shr(16 - bits.min(16)).unwrap_or(0)
+}
+
+impl TargetSpec {
+    /// Largest RAM address on the target.
+    #[must_use]
+    pub fn max_address(&self) -> Address {
+        max_value(self.address_bits)
+    }
+
+    /// Largest literal on the target.
+    #[must_use]
+    pub fn max_literal(&self) -> Literal {
+        max_value(self.literal_bits)
+    }
+
+    /// Mask covering every bit of the accumulator.
+    #[must_use]
+    pub fn accumulator_mask(&self) -> u16 {
+        max_value(self.accumulator_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0 => 0)]
+    #[test_case(7 => 0x7f)]
+    #[test_case(15 => 0x7fff)]
+    #[test_case(16 => 0xffff)]
+    #[test_case(32 => 0xffff; "saturates")]
+    fn max(bits: u32) -> u16 {
+        max_value(bits)
+    }
+}
@@ -0,0 +1,334 @@
+//! Memory-mapped I/O devices.
+//!
+//! A [`Device`] is attached to a range of addresses on a [`Bus`].
+//! Reads and writes to those addresses go to the device instead of memory.
+//!
+//! To collect characters written to address `0x7f`:
+//! ```
+//! # fn main() {
+//! use peppermint::target::TargetSpec;
+//! use peppermint_simulate::{device::Console, TickTalk};
+//!
+//! let program = peppermint::Program::parse_source("72 STORE [0x7f] 105 STORE [0x7f]").unwrap();
+//! let mut sim = TickTalk::new(&program, &TargetSpec::default());
+//! let console = sim.devices.attach(0x7f, Console::new(Vec::new())).unwrap();
+//! sim.run_to_completion().unwrap();
+//!
+//! assert_eq!(console.lock().unwrap().get_ref(), b"Hi");
+//! # }
+//! ```
+
+use std::{
+    fmt::Debug,
+    io::{Read, Write},
+    ops::Range,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use peppermint::{Address, DoubleWord};
+
+use super::Error;
+
+/// Peripheral which responds to reads and writes of a range of addresses.
+pub trait Device {
+    /// Number of addresses the device occupies.
+    fn size(&self) -> Address {
+        1
+    }
+
+    /// Read from the device, `offset` is relative to the start of its range.
+    fn read(&mut self, offset: Address) -> DoubleWord;
+
+    /// Write to the device, `offset` is relative to the start of its range.
+    fn write(&mut self, offset: Address, value: DoubleWord);
+
+    /// Called once after each statement is executed.
+    fn tick(&mut self) {}
+}
+
+/// Device shared between a bus, its clones and the handle returned by [`Bus::attach`].
+type SharedDevice = Arc<Mutex<dyn Device + Send>>;
+
+/// Lock a device, even if a panic while it was locked poisoned it, since that panic has already
+/// been reported wherever it happened.
+fn lock(device: &SharedDevice) -> MutexGuard<'_, dyn Device + Send + 'static> {
+    device.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Set of devices attached to the address space, consulted before memory.
+///
+/// Cloning a bus shares its devices, so a cloned [`TickTalk`](crate::TickTalk) talks to the same
+/// peripherals as the original.
+/// Devices must be [`Send`] so that a simulator can be moved to another thread.
+#[derive(Default, Clone)]
+pub struct Bus {
+    devices: Vec<(Range<usize>, SharedDevice)>,
+}
+
+impl Debug for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.devices.iter().map(|(range, _)| range))
+            .finish()
+    }
+}
+
+impl Bus {
+    /// Attach `device` at addresses starting from `base`.
+    ///
+    /// Returns a handle to the device, to inspect it while or after the program runs.
+    ///
+    /// # Errors
+    ///
+    /// Throws [`Error::DeviceOverlap`] if another device is already attached to any of the
+    /// addresses.
+    pub fn attach<D: Device + Send + 'static>(
+        &mut self,
+        base: Address,
+        device: D,
+    ) -> Result<Arc<Mutex<D>>, Error> {
+        let range = base as usize..base as usize + device.size() as usize;
+        if let Some((existing, _)) = self
+            .devices
+            .iter()
+            .find(|(other, _)| other.start < range.end && range.start < other.end)
+        {
+            return Err(Error::DeviceOverlap {
+                address: existing.start.max(range.start),
+            });
+        }
+        let device = Arc::new(Mutex::new(device));
+        self.devices.push((range, device.clone()));
+        Ok(device)
+    }
+
+    /// Whether no devices are attached.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Find the device at `addr`, locked, and the offset of `addr` within it.
+    fn find(
+        &self,
+        addr: Address,
+    ) -> Option<(MutexGuard<'_, dyn Device + Send + 'static>, Address)> {
+        let (range, device) = self
+            .devices
+            .iter()
+            .find(|(range, _)| range.contains(&(addr as usize)))?;
+        // the offset is never more than the address
+        #[allow(clippy::cast_possible_truncation)]
+        Some((lock(device), (addr as usize - range.start) as Address))
+    }
+
+    /// Read from the device at `addr`, or [`None`] if there isn't one.
+    pub(crate) fn read(&mut self, addr: Address) -> Option<DoubleWord> {
+        self.find(addr)
+            .map(|(mut device, offset)| device.read(offset))
+    }
+
+    /// Write to the device at `addr`, returning whether there was one.
+    pub(crate) fn write(&mut self, addr: Address, value: DoubleWord) -> bool {
+        self.find(addr)
+            .map(|(mut device, offset)| device.write(offset, value))
+            .is_some()
+    }
+
+    pub(crate) fn tick(&mut self) {
+        for (_, device) in &self.devices {
+            lock(device).tick();
+        }
+    }
+}
+
+/// Output port, prints characters written to its first address and numbers written to its second.
+///
+/// Numbers are printed in decimal on their own line.
+/// Reads always return 0.
+pub struct Console<W> {
+    out: W,
+}
+
+impl<W: Write> Console<W> {
+    /// Create a console which prints to `out`.
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Output the console has printed to.
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+}
+
+impl<W: Write> Device for Console<W> {
+    fn size(&self) -> Address {
+        2
+    }
+
+    fn read(&mut self, _offset: Address) -> DoubleWord {
+        0
+    }
+
+    fn write(&mut self, offset: Address, value: DoubleWord) {
+        // output is best effort, a program can't do anything about a broken pipe
+        let _ = if offset == 0 {
+            let c = char::from_u32(value.into()).unwrap_or(char::REPLACEMENT_CHARACTER);
+            write!(self.out, "{c}")
+        } else {
+            writeln!(self.out, "{value}")
+        };
+        let _ = self.out.flush();
+    }
+}
+
+/// Input port, each read returns the next byte of its input, or 0 once the input is exhausted.
+///
+/// Writes are ignored.
+pub struct InputPort<R> {
+    input: R,
+}
+
+impl<R: Read> InputPort<R> {
+    /// Create an input port reading from `input`.
+    pub fn new(input: R) -> Self {
+        Self { input }
+    }
+}
+
+impl<R: Read> Device for InputPort<R> {
+    fn read(&mut self, _offset: Address) -> DoubleWord {
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(1) => byte[0].into(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _offset: Address, _value: DoubleWord) {}
+}
+
+/// Counter of executed statements, wrapping at the size of a double word.
+///
+/// Writing sets the counter.
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: DoubleWord,
+}
+
+impl Device for Timer {
+    fn read(&mut self, _offset: Address) -> DoubleWord {
+        self.count
+    }
+
+    fn write(&mut self, _offset: Address, value: DoubleWord) {
+        self.count = value;
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+}
+
+/// Row of LEDs, one for each bit of a double word.
+///
+/// Each write prints the row to its output, most significant bit first, with `*` for lit LEDs and
+/// `.` for unlit ones.
+/// Reads return the current state.
+pub struct Leds<W> {
+    out: W,
+    state: DoubleWord,
+}
+
+impl<W: Write> Leds<W> {
+    /// Create a row of LEDs which prints to `out`, with every LED off.
+    pub fn new(out: W) -> Self {
+        Self { out, state: 0 }
+    }
+}
+
+impl<W: Write> Device for Leds<W> {
+    fn read(&mut self, _offset: Address) -> DoubleWord {
+        self.state
+    }
+
+    fn write(&mut self, _offset: Address, value: DoubleWord) {
+        self.state = value;
+        let row: String = (0..DoubleWord::BITS)
+            .rev()
+            .map(|bit| if value >> bit & 1 == 1 { '*' } else { '.' })
+            .collect();
+        let _ = writeln!(self.out, "{row}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TickTalk;
+    use peppermint::{target::TargetSpec, Program};
+
+    #[test]
+    fn console() {
+        let program = Program::parse_source("65 STORE [0x10] 42 STORE [0x11]").unwrap();
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        let console = sim.devices.attach(0x10, Console::new(Vec::new())).unwrap();
+        sim.run_to_completion().expect("simulation error");
+        assert_eq!(sim.memory[0x10], 0);
+
+        assert_eq!(console.lock().unwrap().get_ref(), b"A42\n");
+    }
+
+    #[test]
+    fn clone_shares_devices() {
+        let program = Program::parse_source("65 STORE [0x10]").unwrap();
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        let console = sim.devices.attach(0x10, Console::new(Vec::new())).unwrap();
+        let mut clone = sim.clone();
+        sim.run_to_completion().expect("simulation error");
+        clone.run_to_completion().expect("simulation error");
+
+        assert_eq!(console.lock().unwrap().get_ref(), b"AA");
+    }
+
+    #[test]
+    fn input_and_timer() {
+        let program = Program::parse_source(
+            "LOAD [0x10] STORE [0x00] LOAD [0x10] STORE [0x01] LOAD [0x10] STORE [0x02]
+            LOAD [0x20] STORE [0x03]",
+        )
+        .unwrap();
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        sim.devices
+            .attach(0x10, InputPort::new(&b"hi"[..]))
+            .unwrap();
+        sim.devices.attach(0x20, Timer::default()).unwrap();
+        sim.run_to_completion().expect("simulation error");
+
+        assert_eq!(sim.memory[..4], [b'h'.into(), b'i'.into(), 0, 6]);
+    }
+
+    #[test]
+    fn simulator_is_send() {
+        let program = Program::parse_source("65 STORE [0x10]").unwrap();
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        let console = sim.devices.attach(0x10, Console::new(Vec::new())).unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(move || sim.run_to_completion().expect("simulation error"));
+        });
+
+        assert_eq!(console.lock().unwrap().get_ref(), b"A");
+    }
+
+    #[test]
+    fn overlap() {
+        let mut bus = Bus::default();
+        bus.attach(0x10, Console::new(std::io::sink())).unwrap();
+
+        let err = bus
+            .attach(0x11, Timer::default())
+            .expect_err("no error thrown");
+        assert!(matches!(err, Error::DeviceOverlap { address: 0x11 }));
+    }
+}
@@ -0,0 +1,283 @@
+//! Initial memory contents loaded from files.
+//!
+//! Memory images can be given in three formats:
+//! - [`Format::IntelHex`]: Intel HEX records, where each pair of bytes is a big-endian word and
+//!   byte address `n` belongs to word `n / 2`
+//! - [`Format::Binary`]: big-endian words starting from address 0
+//! - [`Format::Text`]: lines of `address: value`, where several values set consecutive addresses
+//!   and `;` or `#` start a comment
+//!
+//! Parsed images are loaded with [`TickTalk::load_memory`](crate::TickTalk::load_memory).
+//! [`to_intel_hex`] goes the other way, so memory can be saved and loaded again.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use peppermint::{Address, DoubleWord};
+
+/// Format of a memory image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Intel HEX records.
+    IntelHex,
+    /// Raw big-endian words.
+    Binary,
+    /// `address: value` lines.
+    Text,
+}
+
+/// Error in a malformed memory image.
+#[derive(Debug, PartialEq, Clone, thiserror::Error)]
+pub enum Error {
+    /// Line which couldn't be parsed.
+    #[error("line {line} is malformed")]
+    Malformed {
+        /// 1-based line number.
+        line: usize,
+    },
+    /// Intel HEX record whose checksum doesn't match its contents.
+    #[error("checksum mismatch on line {line}")]
+    Checksum {
+        /// 1-based line number.
+        line: usize,
+    },
+    /// Intel HEX record type which has no meaning for a memory image.
+    #[error("unsupported record type {kind:02x} on line {line}")]
+    UnsupportedRecord {
+        /// 1-based line number.
+        line: usize,
+        /// Record type.
+        kind: u8,
+    },
+    /// Address too large to be an [`Address`].
+    #[error("address 0x{address:x} is too large")]
+    AddressTooLarge {
+        /// The word address.
+        address: usize,
+    },
+    /// Binary image which doesn't contain a whole number of words.
+    #[error("image is {len} bytes long, which isn't a whole number of words")]
+    TruncatedWord {
+        /// Length of the image in bytes.
+        len: usize,
+    },
+}
+
+/// Cells set by a memory image, in address order.
+pub type Cells = Vec<(Address, DoubleWord)>;
+
+/// Parse a memory image.
+///
+/// # Errors
+///
+/// Throws [`enum@Error`] if the image is malformed.
+pub fn parse(data: &[u8], format: Format) -> Result<Cells, Error> {
+    match format {
+        Format::IntelHex => parse_intel_hex(data),
+        Format::Binary => parse_binary(data),
+        Format::Text => parse_text(data),
+    }
+}
+
+fn to_address(address: usize) -> Result<Address, Error> {
+    Address::try_from(address).map_err(|_| Error::AddressTooLarge { address })
+}
+
+fn lines(data: &[u8]) -> Result<impl Iterator<Item = (usize, &str)>, Error> {
+    let text = std::str::from_utf8(data).map_err(|e| Error::Malformed {
+        line: data[..e.valid_up_to()].split(|b| *b == b'\n').count(),
+    })?;
+    Ok(text.lines().enumerate().map(|(i, line)| (i + 1, line)))
+}
+
+fn parse_intel_hex(data: &[u8]) -> Result<Cells, Error> {
+    let mut bytes = BTreeMap::new();
+    let mut base = 0;
+    for (line, text) in lines(data)? {
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let malformed = Error::Malformed { line };
+        let hex = text.strip_prefix(':').ok_or(malformed.clone())?;
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(malformed);
+        }
+        let record = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| malformed.clone())?;
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(Error::Checksum { line });
+        }
+
+        let len = record[0] as usize;
+        let offset = usize::from(u16::from_be_bytes([record[1], record[2]]));
+        let kind = record[3];
+        let payload = record.get(4..4 + len).ok_or(malformed.clone())?;
+        if record.len() != len + 5 {
+            return Err(malformed);
+        }
+        match kind {
+            0x00 => {
+                for (i, byte) in payload.iter().enumerate() {
+                    bytes.insert(base + offset + i, *byte);
+                }
+            }
+            0x01 => break,
+            0x02 if len == 2 => {
+                base = usize::from(u16::from_be_bytes([payload[0], payload[1]])) << 4;
+            }
+            0x04 if len == 2 => {
+                base = usize::from(u16::from_be_bytes([payload[0], payload[1]])) << 16;
+            }
+            0x03 | 0x05 => {}
+            _ => return Err(Error::UnsupportedRecord { line, kind }),
+        }
+    }
+
+    let mut words = BTreeMap::new();
+    for (byte_address, byte) in bytes {
+        let word: &mut DoubleWord = words.entry(byte_address / 2).or_default();
+        *word |= if byte_address % 2 == 0 {
+            DoubleWord::from(byte) << 8
+        } else {
+            DoubleWord::from(byte)
+        };
+    }
+    words
+        .into_iter()
+        .map(|(address, word)| Ok((to_address(address)?, word)))
+        .collect()
+}
+
+fn parse_binary(data: &[u8]) -> Result<Cells, Error> {
+    if !data.len().is_multiple_of(2) {
+        return Err(Error::TruncatedWord { len: data.len() });
+    }
+    data.chunks_exact(2)
+        .enumerate()
+        .map(|(address, word)| {
+            Ok((
+                to_address(address)?,
+                DoubleWord::from_be_bytes([word[0], word[1]]),
+            ))
+        })
+        .collect()
+}
+
+fn parse_text(data: &[u8]) -> Result<Cells, Error> {
+    let mut cells = BTreeMap::new();
+    for (line, text) in lines(data)? {
+        let text = text.split([';', '#']).next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        let (address, values) = text.split_once(':').ok_or(Error::Malformed { line })?;
+        let address =
+            peppermint::parse_int::<usize>(address.trim()).ok_or(Error::Malformed { line })?;
+        for (i, value) in values.split_whitespace().enumerate() {
+            let value = peppermint::parse_int(value).ok_or(Error::Malformed { line })?;
+            cells.insert(to_address(address + i)?, value);
+        }
+    }
+    Ok(cells.into_iter().collect())
+}
+
+/// Write one Intel HEX record, with its checksum.
+fn write_record(out: &mut String, offset: u16, kind: u8, payload: &[u8]) {
+    // payloads are at most 16 bytes
+    #[allow(clippy::cast_possible_truncation)]
+    let mut record = vec![payload.len() as u8];
+    record.extend(offset.to_be_bytes());
+    record.push(kind);
+    record.extend(payload);
+    let checksum = record
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    record.push(checksum);
+
+    out.push(':');
+    for byte in record {
+        let _ = write!(out, "{byte:02X}");
+    }
+    out.push('\n');
+}
+
+/// Encode cells as Intel HEX, the inverse of [`Format::IntelHex`].
+///
+/// Consecutive cells are grouped into records of up to 8 words.
+#[must_use]
+pub fn to_intel_hex(cells: impl IntoIterator<Item = (Address, DoubleWord)>) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+    let mut run: Vec<u8> = Vec::new();
+    let mut run_start = 0usize;
+    let flush = |out: &mut String, upper: &mut usize, run: &mut Vec<u8>, start: usize| {
+        if run.is_empty() {
+            return;
+        }
+        if start >> 16 != *upper {
+            *upper = start >> 16;
+            // byte addresses of words are at most 17 bits
+            #[allow(clippy::cast_possible_truncation)]
+            write_record(out, 0, 0x04, &(*upper as u16).to_be_bytes());
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        write_record(out, start as u16, 0x00, run);
+        run.clear();
+    };
+
+    for (address, value) in cells {
+        let byte_address = address as usize * 2;
+        // records can't cross a 64KiB boundary
+        if run_start + run.len() != byte_address
+            || run.len() == 16
+            || byte_address.is_multiple_of(0x10000)
+        {
+            flush(&mut out, &mut upper, &mut run, run_start);
+            run_start = byte_address;
+        }
+        run.extend(value.to_be_bytes());
+    }
+    flush(&mut out, &mut upper, &mut run, run_start);
+    write_record(&mut out, 0, 0x01, &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(b":0400000012345678E8\n:00000001FF" => Ok(vec![(0, 0x1234), (1, 0x5678)]); "data")]
+    #[test_case(b":020000040002F8\n:02000000ABCD86" => Err(Error::AddressTooLarge { address: 0x10000 }); "extended address")]
+    #[test_case(b":030004001234565D" => Ok(vec![(2, 0x1234), (3, 0x5600)]); "odd length")]
+    #[test_case(b":0400000012345678E9" => Err(Error::Checksum { line: 1 }))]
+    #[test_case(b"\n0400000012345678E8" => Err(Error::Malformed { line: 2 }))]
+    fn intel_hex(data: &[u8]) -> Result<Cells, Error> {
+        parse(data, Format::IntelHex)
+    }
+
+    #[test_case(b"\x00\x0a\xff\xff" => Ok(vec![(0, 10), (1, 0xffff)]))]
+    #[test_case(b"\x00\x0a\xff" => Err(Error::TruncatedWord { len: 3 }))]
+    fn binary(data: &[u8]) -> Result<Cells, Error> {
+        parse(data, Format::Binary)
+    }
+
+    #[test_case(b"0x10: 5 ; comment\n# whole line\n\n3: 0b11 0xff" => Ok(vec![(3, 3), (4, 0xff), (0x10, 5)]))]
+    #[test_case(b"0x10 5" => Err(Error::Malformed { line: 1 }); "missing colon")]
+    #[test_case(b"0: 70000" => Err(Error::Malformed { line: 1 }); "value too large")]
+    fn text(data: &[u8]) -> Result<Cells, Error> {
+        parse(data, Format::Text)
+    }
+
+    #[test_case(&[(0, 0x1234), (1, 0x5678)] => ":0400000012345678E8\n:00000001FF\n"; "contiguous")]
+    #[test_case(&[(2, 0x1234), (0x8000, 1)] => ":020004001234B4\n:020000040001F9\n:020000000001FD\n:00000001FF\n"; "extended address")]
+    fn intel_hex_out(cells: &[(Address, DoubleWord)]) -> String {
+        let hex = to_intel_hex(cells.iter().copied());
+        assert_eq!(parse(hex.as_bytes(), Format::IntelHex).unwrap(), cells);
+        hex
+    }
+}
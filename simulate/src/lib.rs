@@ -3,6 +3,7 @@
 //! To parse a program then run it to completion:
 //! ```
 //! # fn main() {
+//! use peppermint::target::TargetSpec;
 //! use peppermint_simulate::TickTalk;
 //!
 //! let my_program = "
@@ -14,7 +15,7 @@
 //! ";
 //!
 //! let parsed = peppermint::Program::parse_source(my_program).unwrap();
-//! let mut sim = TickTalk::new(&parsed, 100);
+//! let mut sim = TickTalk::new(&parsed, &TargetSpec::default());
 //! sim.run_to_completion().unwrap();
 //!
 //! assert_eq!(sim.memory[0x20], 15);
@@ -23,10 +24,25 @@
 #![warn(clippy::pedantic)]
 #![deny(missing_docs)]
 
-use std::ops::DerefMut;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    ops::{DerefMut, RangeInclusive},
+};
 
-use peppermint::{Address, DoubleWord, Instruction, Program, Statement};
+pub mod device;
+pub mod image;
+pub mod trace;
+
+use device::Bus;
+use peppermint::{
+    debug_info::{DebugInfo, LineEntry},
+    error::Span,
+    target::{InstructionSet, Overflow, TargetSpec},
+    Address, DoubleWord, Instruction, Program, Statement,
+};
 use thiserror::Error;
+use trace::{MemoryWrite, TraceEntry};
 
 /// Simulator for Peppermint on Tick Talk.
 ///
@@ -41,6 +57,45 @@ pub struct TickTalk<'a, M> {
     pub program_counter: usize,
     /// Accumulator of the system.
     pub accumulator: peppermint::DoubleWord,
+    /// Carry/borrow flag, set by the last `ADD` or `SUB` if it overflowed the accumulator.
+    pub carry: bool,
+    /// Description of the machine being simulated.
+    pub target: TargetSpec,
+    /// Memory-mapped devices, which take priority over memory at their addresses.
+    pub devices: Bus,
+    /// Record of executed statements, only kept if this is [`Some`].
+    pub trace: Option<Vec<TraceEntry>>,
+    /// Number of statements executed so far.
+    pub steps: usize,
+    /// Whether [`TickTalk::run_to_completion`] and [`TickTalk::run_with_limit`] should check for
+    /// repeated machine states, which mean the program will never halt.
+    ///
+    /// Ignored while any devices are attached, since they have state of their own.
+    /// States are remembered by a 64-bit hash, so a collision could report a loop which isn't
+    /// there, though that's vanishingly unlikely.
+    pub detect_loops: bool,
+    /// Undo log for [`TickTalk::step_back`], newest last.
+    history: VecDeque<TraceEntry>,
+    /// Most steps kept in `history`.
+    history_limit: usize,
+}
+
+/// Copy of the machine state, for reporting results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    /// Accumulator of the system.
+    pub accumulator: DoubleWord,
+    /// Program counter "register".
+    pub program_counter: usize,
+    /// Carry/borrow flag.
+    pub carry: bool,
+    /// Number of statements executed.
+    pub steps: usize,
+    /// Whether the program has halted.
+    pub halted: bool,
+    /// Selected memory cells.
+    pub memory: BTreeMap<Address, DoubleWord>,
 }
 
 /// Error in simulation.
@@ -49,39 +104,103 @@ pub enum Error {
     /// Tried to access address outside of memory.
     #[error("tried to access address outside of memory")]
     AccessOutOfBounds,
+    /// Arithmetic overflowed the accumulator while [`Overflow::Trap`] was set.
+    #[error("accumulator overflowed at statement {pc}")]
+    Overflow {
+        /// Program counter of the offending statement.
+        pc: usize,
+    },
+    /// Program ran for more steps than it was allowed.
+    #[error("program didn't halt within {limit} steps")]
+    StepLimitExceeded {
+        /// The step limit.
+        limit: usize,
+    },
+    /// Tried to attach a device to an address which already has one.
+    #[error("a device is already attached at 0x{address:x}")]
+    DeviceOverlap {
+        /// First address used by both devices.
+        address: usize,
+    },
+    /// Machine returned to a state it had already been in, so the program will never halt.
+    #[error("program is stuck in an infinite loop at `{label}`")]
+    InfiniteLoop {
+        /// Program counter of the label the loop was detected at.
+        pc: usize,
+        /// Name of the label.
+        label: String,
+    },
+    /// Instruction which isn't in the target's instruction set.
+    #[error("instruction at statement {pc} isn't supported by the target")]
+    UnsupportedInstruction {
+        /// Program counter of the offending statement.
+        pc: usize,
+    },
 }
 
 impl<'a> TickTalk<'a, Vec<DoubleWord>> {
-    /// Create a new simulator and load a program into it.
+    /// Create a new simulator of `target` and load a program into it.
     ///
-    /// To parse into a program, see [`peppermint::Program::parse_source`].
+    /// Memory is allocated according to [`TargetSpec::memory_size`], and starts with the
+    /// program's [initial memory](Program::initial_memory).
+    /// To parse into a program, see [`peppermint::Program::parse_source_for`].
     #[must_use]
-    pub fn new(program: &'a Program, memory_size: usize) -> Self {
-        Self {
-            program,
-            memory: vec![0; memory_size],
-            program_counter: 0,
-            accumulator: 0,
-        }
+    pub fn new(program: &'a Program, target: &TargetSpec) -> Self {
+        Self::with_external_mem(program, vec![0; target.memory_size], target)
     }
 }
 
 impl<'a, M: DerefMut<Target = [DoubleWord]>> TickTalk<'a, M> {
-    /// Create a new simulator and load a program into it with an external memory buffer.
+    /// Create a new simulator of `target` and load a program into it with an external memory buffer.
     ///
-    /// To parse into a program, see [`peppermint::Program::parse_source`].
-    pub fn with_external_mem(program: &'a Program, memory: M) -> Self {
+    /// [`TargetSpec::memory_size`] is ignored in favour of the size of `memory`.
+    /// The program's [initial memory](Program::initial_memory) is written into `memory`, skipping
+    /// any cells which don't fit.
+    /// To parse into a program, see [`peppermint::Program::parse_source_for`].
+    pub fn with_external_mem(program: &'a Program, mut memory: M, target: &TargetSpec) -> Self {
+        for &(addr, value) in program.initial_memory() {
+            if let Some(cell) = memory.get_mut(addr as usize) {
+                *cell = value;
+            }
+        }
         Self {
             program,
             memory,
             program_counter: 0,
             accumulator: 0,
+            carry: false,
+            target: target.clone(),
+            devices: Bus::default(),
+            trace: None,
+            steps: 0,
+            detect_loops: false,
+            history: VecDeque::new(),
+            history_limit: 0,
         }
     }
 
+    /// Set memory cells, such as those parsed from an image by [`image::parse`].
+    ///
+    /// # Errors
+    /// Throws [`Error::AccessOutOfBounds`] if a cell is outside of memory, cells before it are
+    /// still set.
+    pub fn load_memory(
+        &mut self,
+        cells: impl IntoIterator<Item = (Address, DoubleWord)>,
+    ) -> Result<(), Error> {
+        for (addr, value) in cells {
+            *self
+                .memory
+                .get_mut(addr as usize)
+                .ok_or(Error::AccessOutOfBounds)? = value;
+        }
+        Ok(())
+    }
+
     /// Step the program by a single instruction.
     ///
     /// Returns whether the program has halted.
+    /// If the step fails, the program counter is left pointing at the offending statement.
     ///
     /// # Errors
     /// Throws [`enum@Error`] if the program behaves illegally during simulation.
@@ -90,46 +209,240 @@ impl<'a, M: DerefMut<Target = [DoubleWord]>> TickTalk<'a, M> {
             return Ok(true);
         }
 
-        let statement = &self.program.statements()[self.program_counter];
-        self.program_counter += 1;
+        let pc = self.program_counter;
+        let accumulator_before = self.accumulator;
+        let carry_before = self.carry;
+        let write = self.execute()?;
+        self.steps += 1;
+        self.devices.tick();
+
+        if self.trace.is_none() && self.history_limit == 0 {
+            return Ok(false);
+        }
+        let entry = TraceEntry {
+            pc,
+            statement: self.program.statements()[pc].clone(),
+            next_pc: self.program_counter,
+            accumulator_before,
+            accumulator_after: self.accumulator,
+            carry_before,
+            carry_after: self.carry,
+            writes: write.into_iter().collect(),
+        };
+        if self.history_limit > 0 {
+            if self.history.len() == self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(entry.clone());
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.push(entry);
+        }
+
+        Ok(false)
+    }
+
+    /// Keep an undo log of up to `limit` steps, so they can be undone with [`TickTalk::step_back`].
+    ///
+    /// Passing 0 disables the log.
+    /// If the log is already longer than `limit`, the oldest steps are forgotten.
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// Number of steps which can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Undo the last step, restoring the program counter, accumulator, carry flag and memory.
+    ///
+    /// Device state isn't restored.
+    /// Returns the undone step, or [`None`] if the undo log is empty.
+    /// See [`TickTalk::enable_history`].
+    pub fn step_back(&mut self) -> Option<TraceEntry> {
+        let entry = self.history.pop_back()?;
+        for write in entry.writes.iter().rev() {
+            self.memory[write.address as usize] = write.old;
+        }
+        self.program_counter = entry.pc;
+        self.steps -= 1;
+        self.accumulator = entry.accumulator_before;
+        self.carry = entry.carry_before;
+        Some(entry)
+    }
+
+    /// Step backwards until the statement at `pc` is about to be executed again.
+    ///
+    /// Returns whether `pc` was reached.
+    /// If it wasn't, the machine is left at the oldest state in the undo log.
+    pub fn run_back_to(&mut self, pc: usize) -> bool {
+        while let Some(entry) = self.step_back() {
+            if entry.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Execute the statement at the program counter and advance it.
+    ///
+    /// Returns the memory write made by the statement, if any.
+    /// Writes to devices aren't returned.
+    fn execute(&mut self) -> Result<Option<MemoryWrite>, Error> {
+        let pc = self.program_counter;
+        let statement = &self.program.statements()[pc];
+        let mask = self.target.accumulator_mask();
+        let mut write = None;
 
         match statement {
-            Statement::Literal(val) => self.accumulator = *val,
+            Statement::Literal(val) => self.accumulator = *val & mask,
+            Statement::InstrLine(ins)
+                if ins.is_extended() && self.target.instruction_set == InstructionSet::Base =>
+            {
+                return Err(Error::UnsupportedInstruction { pc });
+            }
             Statement::InstrLine(ins) => match ins {
-                Instruction::Load(addr) => self.accumulator = self.read_address(*addr)?,
+                Instruction::Load(addr) => self.accumulator = self.read_address(*addr)? & mask,
                 Instruction::And(addr) => self.accumulator &= self.read_address(*addr)?,
-                Instruction::Xor(addr) => self.accumulator ^= self.read_address(*addr)?,
-                Instruction::Or(addr) => self.accumulator |= self.read_address(*addr)?,
-                Instruction::Add(addr) => self.accumulator += self.read_address(*addr)?,
-                Instruction::Sub(addr) => self.accumulator -= self.read_address(*addr)?,
+                Instruction::Xor(addr) => self.accumulator ^= self.read_address(*addr)? & mask,
+                Instruction::Or(addr) => self.accumulator |= self.read_address(*addr)? & mask,
+                Instruction::Add(addr) => {
+                    let result = i32::from(self.accumulator) + i32::from(self.read_address(*addr)?);
+                    self.accumulator = self.fit_accumulator(pc, result)?;
+                }
+                Instruction::Sub(addr) => {
+                    let result = i32::from(self.accumulator) - i32::from(self.read_address(*addr)?);
+                    self.accumulator = self.fit_accumulator(pc, result)?;
+                }
                 Instruction::Store(addr) => {
-                    if let Some(value) = self.memory.get_mut(*addr as usize) {
+                    // writes to devices don't take part in tracing
+                    if !self.devices.write(*addr, self.accumulator) {
+                        let value = self
+                            .memory
+                            .get_mut(*addr as usize)
+                            .ok_or(Error::AccessOutOfBounds)?;
+                        write = Some(MemoryWrite {
+                            address: *addr,
+                            old: *value,
+                            new: self.accumulator,
+                        });
                         *value = self.accumulator;
-                    } else {
-                        return Err(Error::AccessOutOfBounds);
                     }
                 }
-                Instruction::Jump(pc) => self.program_counter = *pc,
+                Instruction::Jump(target) => {
+                    self.program_counter = *target;
+                    return Ok(None);
+                }
+                Instruction::JumpZ(target) if self.accumulator == 0 => {
+                    self.program_counter = *target;
+                    return Ok(None);
+                }
+                // the top bit of the accumulator is the sign in two's complement
+                Instruction::JumpN(target) if self.accumulator & !(mask >> 1) & mask != 0 => {
+                    self.program_counter = *target;
+                    return Ok(None);
+                }
+                Instruction::JumpZ(_) | Instruction::JumpN(_) => {}
+                Instruction::Halt => {
+                    self.program_counter = self.program.statements().len();
+                    return Ok(None);
+                }
             },
             Statement::Label(_) => {}
         }
+        self.program_counter += 1;
 
-        Ok(false)
+        Ok(write)
+    }
+
+    /// Fit the result of an addition or subtraction into the accumulator, updating the carry flag.
+    ///
+    /// Follows the overflow behaviour of the target.
+    fn fit_accumulator(&mut self, pc: usize, result: i32) -> Result<DoubleWord, Error> {
+        let mask = i32::from(self.target.accumulator_mask());
+        let overflowed = !(0..=mask).contains(&result);
+        let fitted = match self.target.overflow {
+            Overflow::Trap if overflowed => return Err(Error::Overflow { pc }),
+            // masking a negative number gives the two's complement wrapped result
+            Overflow::Wrap | Overflow::Trap => result & mask,
+            Overflow::Saturate => result.clamp(0, mask),
+        };
+
+        self.carry = overflowed;
+        // `fitted` is always within the mask, which is no wider than a double word
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Ok(fitted as DoubleWord)
     }
 
     /// Run the simulator until the program exits.
     ///
     /// **Warning**: Infinite loops are possible in Peppermint, so this function may never terminate.
+    /// Set [`TickTalk::detect_loops`] to catch loops which repeat exactly, or use
+    /// [`TickTalk::run_with_limit`].
     ///
     /// # Errors
     /// Throws [`enum@Error`] if the program behaves illegally during simulation.
     pub fn run_to_completion(&mut self) -> Result<(), Error> {
-        let mut halted = false;
-        while !halted {
-            halted = self.step()?;
-        }
+        self.run(None)
+    }
 
-        Ok(())
+    /// Run the simulator until the program exits, executing at most `max_steps` statements.
+    ///
+    /// # Errors
+    /// Throws [`Error::StepLimitExceeded`] if the program is still running after `max_steps`, or
+    /// another [`enum@Error`] if the program behaves illegally during simulation.
+    pub fn run_with_limit(&mut self, max_steps: usize) -> Result<(), Error> {
+        self.run(Some(max_steps))
+    }
+
+    fn run(&mut self, max_steps: Option<usize>) -> Result<(), Error> {
+        // every loop jumps back to a label, so checking states there is enough
+        let mut seen = HashSet::new();
+        // without devices, only cells the program stores to can change while it runs
+        let mut stored: Vec<_> = self
+            .program
+            .statements()
+            .iter()
+            .filter_map(|stat| match stat {
+                Statement::InstrLine(Instruction::Store(addr)) => Some(*addr),
+                _ => None,
+            })
+            .collect();
+        stored.sort_unstable();
+        stored.dedup();
+        let start = self.steps;
+        loop {
+            if self.detect_loops && self.devices.is_empty() {
+                if let Some(Statement::Label(label)) =
+                    self.program.statements().get(self.program_counter)
+                {
+                    // only a hash is kept, so memory use doesn't grow with the size of RAM
+                    let mut state = DefaultHasher::new();
+                    (self.program_counter, self.accumulator, self.carry).hash(&mut state);
+                    for addr in &stored {
+                        self.memory.get(*addr as usize).hash(&mut state);
+                    }
+                    if !seen.insert(state.finish()) {
+                        return Err(Error::InfiniteLoop {
+                            pc: self.program_counter,
+                            label: label.clone(),
+                        });
+                    }
+                }
+            }
+            if let Some(limit) = max_steps {
+                if self.steps - start >= limit && !self.halted() {
+                    return Err(Error::StepLimitExceeded { limit });
+                }
+            }
+            if self.step()? {
+                return Ok(());
+            }
+        }
     }
 
     /// Check if the machine is halted.
@@ -139,8 +452,47 @@ impl<'a, M: DerefMut<Target = [DoubleWord]>> TickTalk<'a, M> {
         self.program_counter >= self.program.statements().len()
     }
 
-    /// Read from an address in the memory.
-    fn read_address(&self, addr: Address) -> Result<DoubleWord, Error> {
+    /// Span of the source code of the statement at the program counter.
+    ///
+    /// After a failed step this is the statement which failed.
+    /// Returns [`None`] if the machine is halted or the program wasn't parsed from source.
+    pub fn source_span(&self) -> Option<Span> {
+        self.program.span_of(self.program_counter)
+    }
+
+    /// Source position of the statement at the program counter, for a program disassembled from
+    /// an image with [`disassemble_with_info`](peppermint::disasm::disassemble_with_info).
+    ///
+    /// Returns [`None`] if the machine is halted or `info` has no position for the statement.
+    #[must_use]
+    pub fn source_location<'i>(&self, info: &'i DebugInfo) -> Option<&'i LineEntry> {
+        info.location_of_statement(self.program, self.program_counter)
+    }
+
+    /// Take a snapshot of the machine state.
+    ///
+    /// Only memory within `range` is included, and only non-zero cells if `non_zero` is set.
+    /// Devices aren't read.
+    pub fn snapshot(&self, range: RangeInclusive<Address>, non_zero: bool) -> Snapshot {
+        let memory = range
+            .filter_map(|addr| Some((addr, *self.memory.get(addr as usize)?)))
+            .filter(|(_, value)| !non_zero || *value != 0)
+            .collect();
+        Snapshot {
+            accumulator: self.accumulator,
+            program_counter: self.program_counter,
+            carry: self.carry,
+            steps: self.steps,
+            halted: self.halted(),
+            memory,
+        }
+    }
+
+    /// Read from an address in the memory, or the device attached to it.
+    fn read_address(&mut self, addr: Address) -> Result<DoubleWord, Error> {
+        if let Some(value) = self.devices.read(addr) {
+            return Ok(value);
+        }
         self.memory
             .get(addr as usize)
             .copied()
@@ -151,6 +503,7 @@ impl<'a, M: DerefMut<Target = [DoubleWord]>> TickTalk<'a, M> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     #[test]
     fn simple_jump() {
@@ -165,9 +518,200 @@ mod tests {
         STORE [0x00]";
 
         let program = peppermint::Program::parse_source(source).expect("parse error");
-        let mut sim = TickTalk::new(&program, 10);
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
         sim.run_to_completion().expect("simulation error");
 
         assert_eq!(sim.memory[0x00], 11);
     }
+
+    #[test_case("0x7f STORE [0x00] ADD [0x00]", Overflow::Wrap => (0xfe, false))]
+    #[test_case("0xf0 STORE [0x00] ADD [0x00]", Overflow::Wrap => (0xe0, true))]
+    #[test_case("0xffff", Overflow::Wrap => (0xff, false); "literal is masked")]
+    #[test_case("1 STORE [0x00] 0 SUB [0x00]", Overflow::Wrap => (0xff, true))]
+    #[test_case("1 STORE [0x00] 0 SUB [0x00]", Overflow::Saturate => (0, true))]
+    #[test_case("0xf0 STORE [0x00] ADD [0x00]", Overflow::Saturate => (0xff, true))]
+    #[test_case("0xf0 STORE [0x00] ADD [0x00] 1 STORE [0x01] ADD [0x01]", Overflow::Wrap => (2, false); "carry cleared")]
+    fn overflow(source: &str, overflow: Overflow) -> (DoubleWord, bool) {
+        let target = TargetSpec {
+            accumulator_bits: 8,
+            overflow,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source_for(
+            source,
+            &TargetSpec {
+                literal_bits: 16,
+                ..TargetSpec::default()
+            },
+        )
+        .expect("parse error");
+        let mut sim = TickTalk::new(&program, &target);
+        sim.run_to_completion().expect("simulation error");
+
+        (sim.accumulator, sim.carry)
+    }
+
+    #[test]
+    fn overflow_trap() {
+        let target = TargetSpec {
+            overflow: Overflow::Trap,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source("1 STORE [0x00] 0 SUB [0x00]").expect("parse error");
+        let mut sim = TickTalk::new(&program, &target);
+
+        let err = sim.run_to_completion().expect_err("no error thrown");
+        assert!(matches!(err, Error::Overflow { pc: 3 }));
+        assert_eq!(sim.program_counter, 3);
+        assert_eq!(sim.source_span(), Some(17..27));
+        assert_eq!(sim.accumulator, 0);
+    }
+
+    #[test]
+    fn source_location() {
+        let target = TargetSpec {
+            overflow: Overflow::Trap,
+            ..TargetSpec::default()
+        };
+        let source = "1\nSTORE [0x00]\nstart: 0\n  SUB [0x00]";
+        let program = Program::parse_source(source).expect("parse error");
+        let info = DebugInfo::new(&program, source, "test.ppr");
+        let image = peppermint::assemble::to_bytes(
+            &peppermint::assemble::assemble(&program, &target).expect("assembly error"),
+        );
+        let disassembled =
+            peppermint::disasm::disassemble_with_info(&image, &info).expect("disassembly error");
+        let mut sim = TickTalk::new(&disassembled, &target);
+
+        sim.run_to_completion().expect_err("no error thrown");
+        assert_eq!(sim.source_span(), None);
+        let location = sim.source_location(&info).expect("no location");
+        assert_eq!((location.line, location.column), (4, 3));
+    }
+
+    #[test_case("3 STORE [0x00] 1 STORE [0x01] loop: LOAD [0x00] JUMPZ :end SUB [0x01] STORE [0x00] JUMP :loop end: 7 STORE [0x10]" => 7; "jumpz loop")]
+    #[test_case("1 STORE [0x00] 0 SUB [0x00] JUMPN :neg 1 STORE [0x10] HALT neg: 2 STORE [0x10]" => 2; "jumpn negative")]
+    #[test_case("1 JUMPN :neg 1 STORE [0x10] HALT neg: 2 STORE [0x10]" => 1; "jumpn positive")]
+    #[test_case("HALT 1 STORE [0x10]" => 0; "halt")]
+    fn extended(source: &str) -> DoubleWord {
+        let target = TargetSpec {
+            instruction_set: InstructionSet::Extended,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source_for(source, &target).expect("parse error");
+        let mut sim = TickTalk::new(&program, &target);
+        sim.run_to_completion().expect("simulation error");
+
+        assert!(sim.halted());
+        sim.memory[0x10]
+    }
+
+    #[test]
+    fn unsupported_instruction() {
+        let extended = TargetSpec {
+            instruction_set: InstructionSet::Extended,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source_for("10 HALT", &extended).expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+
+        let err = sim.run_to_completion().expect_err("no error thrown");
+        assert!(matches!(err, Error::UnsupportedInstruction { pc: 1 }));
+    }
+
+    #[test_case("loop: JUMP :loop", None => matches Err(Error::InfiniteLoop { pc: 0, .. }))]
+    #[test_case("1 STORE [0x00] loop: ADD [0x00] JUMP :loop", None => matches Err(Error::InfiniteLoop { pc: 2, .. }); "after wrapping")]
+    #[test_case("loop: JUMP :loop", Some(100) => matches Err(Error::StepLimitExceeded { limit: 100 }))]
+    #[test_case("10 STORE [0x00]", Some(2) => matches Ok(()); "halts at limit")]
+    fn limits(source: &str, max_steps: Option<usize>) -> Result<(), Error> {
+        let program = Program::parse_source(source).expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        sim.detect_loops = max_steps.is_none();
+        match max_steps {
+            Some(limit) => sim.run_with_limit(limit),
+            None => sim.run_to_completion(),
+        }
+    }
+
+    #[test]
+    fn long_loop_large_memory() {
+        // a counter which only repeats once it wraps, with far more memory than it uses
+        let target = TargetSpec {
+            memory_size: 128_000,
+            ..TargetSpec::default()
+        };
+        let program = Program::parse_source(
+            "1 STORE [0x01] loop: LOAD [0x00] ADD [0x01] STORE [0x00] JUMP :loop",
+        )
+        .expect("parse error");
+        let mut sim = TickTalk::new(&program, &target);
+        sim.detect_loops = true;
+
+        let err = sim.run_to_completion().expect_err("no error thrown");
+        assert!(matches!(err, Error::InfiniteLoop { pc: 2, .. }));
+        // every value of the counter was visited before the loop was found
+        assert!(sim.steps > 5 * 0x10000);
+    }
+
+    #[test]
+    fn load_memory() {
+        let program =
+            Program::parse_source("LOAD [0x10] ADD [0x11] STORE [0x12]").expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        let cells = image::parse(b"0x10: 3 4", image::Format::Text).expect("image error");
+        sim.load_memory(cells).expect("load error");
+        sim.run_to_completion().expect("simulation error");
+
+        assert_eq!(sim.memory[0x12], 7);
+        assert!(sim.load_memory([(0x80, 1)]).is_err());
+    }
+
+    #[test]
+    fn initial_memory() {
+        let program = Program::parse_source(".word x 5\nLOAD [x]\nADD [x]\nSTORE [0x20]")
+            .expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        assert_eq!(sim.memory[0], 5);
+        sim.run_to_completion().expect("simulation error");
+
+        assert_eq!(sim.memory[0x20], 10);
+        // only the program's own statements run
+        assert_eq!(sim.steps, 3);
+    }
+
+    #[test]
+    fn snapshot() {
+        let program = Program::parse_source("5 STORE [0x7f] 6 STORE [0x01]").expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        sim.run_to_completion().expect("simulation error");
+
+        let snapshot = sim.snapshot(0x01..=0xff, true);
+        assert_eq!(snapshot.accumulator, 6);
+        assert_eq!(snapshot.steps, 4);
+        assert!(snapshot.halted);
+        assert_eq!(snapshot.memory, BTreeMap::from([(0x01, 6), (0x7f, 5)]));
+        assert_eq!(sim.snapshot(0x00..=0x02, false).memory.len(), 3);
+    }
+
+    #[test]
+    fn step_back() {
+        let program = Program::parse_source("10 STORE [0x00] 20 STORE [0x00] 30 STORE [0x00]")
+            .expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        sim.enable_history(4);
+        sim.run_to_completion().expect("simulation error");
+        assert_eq!(sim.history_len(), 4);
+
+        assert!(sim.run_back_to(3));
+        assert_eq!(sim.program_counter, 3);
+        assert_eq!(sim.accumulator, 20);
+        assert_eq!(sim.memory[0x00], 10);
+
+        // the first two steps fell out of the log
+        assert!(!sim.run_back_to(0));
+        assert_eq!(sim.program_counter, 2);
+        assert_eq!(sim.accumulator, 10);
+        assert_eq!(sim.memory[0x00], 10);
+        assert!(sim.step_back().is_none());
+    }
 }
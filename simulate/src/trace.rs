@@ -0,0 +1,299 @@
+//! Execution traces of simulated programs.
+//!
+//! Set [`TickTalk::trace`](crate::TickTalk::trace) to record a [`TraceEntry`] for every statement
+//! executed.
+//! Traces can be stored in a compact binary format with [`write_binary`] and [`read_binary`], or
+//! serialised as JSON with the `serde` feature.
+//! A recorded run can be stepped through in either direction with [`Replay`].
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+};
+
+use peppermint::{Address, DoubleWord, Program, Statement};
+
+/// Change made to one memory cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryWrite {
+    /// Address written to.
+    pub address: Address,
+    /// Value before the write.
+    pub old: DoubleWord,
+    /// Value after the write.
+    pub new: DoubleWord,
+}
+
+/// Record of one executed statement.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    /// Program counter of the statement.
+    pub pc: usize,
+    /// The statement which was executed.
+    pub statement: Statement<usize>,
+    /// Program counter after the statement.
+    pub next_pc: usize,
+    /// Accumulator before the statement.
+    pub accumulator_before: DoubleWord,
+    /// Accumulator after the statement.
+    pub accumulator_after: DoubleWord,
+    /// Carry flag before the statement.
+    pub carry_before: bool,
+    /// Carry flag after the statement.
+    pub carry_after: bool,
+    /// Memory written by the statement.
+    pub writes: Vec<MemoryWrite>,
+}
+
+/// Write `value` as a big-endian `u32`, failing if it doesn't fit.
+fn write_u32(out: &mut impl Write, value: usize) -> io::Result<()> {
+    let value = u32::try_from(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    out.write_all(&value.to_be_bytes())
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Write trace entries in the binary trace format.
+///
+/// Each entry is stored big-endian as: `pc: u32`, `next_pc: u32`, `accumulator_before: u16`,
+/// `accumulator_after: u16`, a flags byte (bit 0 is `carry_before`, bit 1 `carry_after`), a count
+/// byte, then `address: u16, old: u16, new: u16` for each write.
+/// Statements aren't stored, [`read_binary`] recovers them from the program.
+///
+/// # Errors
+///
+/// Throws [`io::Error`] if writing fails, or an entry can't be represented in the format.
+pub fn write_binary<'e>(
+    out: &mut impl Write,
+    entries: impl IntoIterator<Item = &'e TraceEntry>,
+) -> io::Result<()> {
+    for entry in entries {
+        write_u32(out, entry.pc)?;
+        write_u32(out, entry.next_pc)?;
+        out.write_all(&entry.accumulator_before.to_be_bytes())?;
+        out.write_all(&entry.accumulator_after.to_be_bytes())?;
+        out.write_all(&[u8::from(entry.carry_before) | (u8::from(entry.carry_after) << 1)])?;
+        let count = u8::try_from(entry.writes.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        out.write_all(&[count])?;
+        for write in &entry.writes {
+            out.write_all(&write.address.to_be_bytes())?;
+            out.write_all(&write.old.to_be_bytes())?;
+            out.write_all(&write.new.to_be_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Read trace entries in the binary trace format, see [`write_binary`].
+///
+/// `program` must be the program the trace was recorded from.
+///
+/// # Errors
+///
+/// Throws [`io::Error`] if reading fails or the trace doesn't match the program.
+pub fn read_binary(input: &mut impl Read, program: &Program) -> io::Result<Vec<TraceEntry>> {
+    let mut entries = Vec::new();
+    loop {
+        let pc = match read_bytes::<4>(input) {
+            Ok(pc) => u32::from_be_bytes(pc) as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        let next_pc = u32::from_be_bytes(read_bytes(input)?) as usize;
+        let accumulator_before = u16::from_be_bytes(read_bytes(input)?);
+        let accumulator_after = u16::from_be_bytes(read_bytes(input)?);
+        let [flags, count] = read_bytes(input)?;
+        let writes = (0..count)
+            .map(|_| {
+                Ok(MemoryWrite {
+                    address: u16::from_be_bytes(read_bytes(input)?),
+                    old: u16::from_be_bytes(read_bytes(input)?),
+                    new: u16::from_be_bytes(read_bytes(input)?),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        let statement = program.statements().get(pc).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("trace refers to statement {pc}, which isn't in the program"),
+            )
+        })?;
+
+        entries.push(TraceEntry {
+            pc,
+            statement,
+            next_pc,
+            accumulator_before,
+            accumulator_after,
+            carry_before: flags & 1 != 0,
+            carry_after: flags & 2 != 0,
+            writes,
+        });
+    }
+    Ok(entries)
+}
+
+/// Cursor over a recorded trace which can move forwards and backwards.
+///
+/// Only memory which the trace writes to is known to the replay.
+#[derive(Debug, Clone)]
+pub struct Replay<'a> {
+    entries: &'a [TraceEntry],
+    /// Number of entries applied.
+    position: usize,
+    memory: BTreeMap<Address, DoubleWord>,
+}
+
+impl<'a> Replay<'a> {
+    /// Start replaying `entries` from before the first statement.
+    #[must_use]
+    pub fn new(entries: &'a [TraceEntry]) -> Self {
+        let mut memory = BTreeMap::new();
+        for write in entries.iter().flat_map(|e| &e.writes) {
+            memory.entry(write.address).or_insert(write.old);
+        }
+        Self {
+            entries,
+            position: 0,
+            memory,
+        }
+    }
+
+    /// Number of statements replayed so far.
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Apply the next entry, returning it.
+    ///
+    /// Returns [`None`] at the end of the trace.
+    pub fn step_forward(&mut self) -> Option<&'a TraceEntry> {
+        let entry = self.entries.get(self.position)?;
+        for write in &entry.writes {
+            self.memory.insert(write.address, write.new);
+        }
+        self.position += 1;
+        Some(entry)
+    }
+
+    /// Undo the last applied entry, returning it.
+    ///
+    /// Returns [`None`] at the start of the trace.
+    pub fn step_back(&mut self) -> Option<&'a TraceEntry> {
+        let entry = self.entries.get(self.position.checked_sub(1)?)?;
+        for write in entry.writes.iter().rev() {
+            self.memory.insert(write.address, write.old);
+        }
+        self.position -= 1;
+        Some(entry)
+    }
+
+    /// Move to `position`, clamped to the length of the trace.
+    pub fn seek(&mut self, position: usize) {
+        while self.position < position && self.step_forward().is_some() {}
+        while self.position > position && self.step_back().is_some() {}
+    }
+
+    /// Program counter at the current position.
+    #[must_use]
+    pub fn program_counter(&self) -> Option<usize> {
+        self.state(|e| e.pc, |e| e.next_pc)
+    }
+
+    /// Accumulator at the current position.
+    #[must_use]
+    pub fn accumulator(&self) -> Option<DoubleWord> {
+        self.state(|e| e.accumulator_before, |e| e.accumulator_after)
+    }
+
+    /// Carry flag at the current position.
+    #[must_use]
+    pub fn carry(&self) -> Option<bool> {
+        self.state(|e| e.carry_before, |e| e.carry_after)
+    }
+
+    /// Value at `address` at the current position, if the trace ever writes to it.
+    #[must_use]
+    pub fn memory(&self, address: Address) -> Option<DoubleWord> {
+        self.memory.get(&address).copied()
+    }
+
+    /// Look up part of the state, from the last applied entry or the first one if none are.
+    fn state<T>(
+        &self,
+        before: impl FnOnce(&TraceEntry) -> T,
+        after: impl FnOnce(&TraceEntry) -> T,
+    ) -> Option<T> {
+        match self.position.checked_sub(1) {
+            Some(last) => self.entries.get(last).map(after),
+            None => self.entries.first().map(before),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TickTalk;
+    use peppermint::target::TargetSpec;
+
+    fn record(source: &str) -> (Program, Vec<TraceEntry>) {
+        let program = Program::parse_source(source).expect("parse error");
+        let mut sim = TickTalk::new(&program, &TargetSpec::default());
+        sim.trace = Some(Vec::new());
+        sim.run_to_completion().expect("simulation error");
+        let trace = sim.trace.take().unwrap();
+        (program, trace)
+    }
+
+    #[test]
+    fn records_writes() {
+        let (_, trace) = record("10 STORE [0x01] 20 STORE [0x01]");
+
+        assert_eq!(trace.len(), 4);
+        assert_eq!(
+            trace[3].writes,
+            vec![MemoryWrite {
+                address: 1,
+                old: 10,
+                new: 20
+            }]
+        );
+        assert_eq!(trace[2].accumulator_before, 10);
+        assert_eq!(trace[2].accumulator_after, 20);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let (program, trace) = record("10 STORE [0x01] JUMP :end 5 end: ADD [0x01]");
+        let mut buf = Vec::new();
+        write_binary(&mut buf, &trace).unwrap();
+
+        assert_eq!(read_binary(&mut buf.as_slice(), &program).unwrap(), trace);
+    }
+
+    #[test]
+    fn replay_both_ways() {
+        let (_, trace) = record("10 STORE [0x01] 20 STORE [0x01]");
+        let mut replay = Replay::new(&trace);
+
+        assert_eq!(replay.memory(1), Some(0));
+        replay.seek(4);
+        assert_eq!(replay.memory(1), Some(20));
+        assert_eq!(replay.program_counter(), Some(4));
+        replay.step_back();
+        assert_eq!(replay.memory(1), Some(10));
+        assert_eq!(replay.accumulator(), Some(20));
+        replay.seek(0);
+        assert_eq!(replay.accumulator(), Some(0));
+        assert!(replay.step_back().is_none());
+    }
+}